@@ -0,0 +1,44 @@
+use std::sync::Mutex;
+
+use error_stack::{Result, ResultExt};
+use rppal::gpio::{Gpio, OutputPin};
+
+use super::Actuator;
+use crate::GError;
+
+/// A relay on a gpio pin, the relay is off while the pin is high.
+#[derive(Debug)]
+pub struct GpioActuator {
+    pin: Mutex<OutputPin>,
+}
+
+impl GpioActuator {
+    pub fn open(number: u8) -> Result<Self, GError> {
+        let mut pin = Gpio::new()
+            .and_then(|gpio| gpio.get(number))
+            .change_context(GError::ActuatorError)
+            .attach_printable_lazy(|| format!("Couldn't open gpio pin {}", number))?
+            .into_output();
+        pin.set_reset_on_drop(false);
+
+        Ok(Self {
+            pin: Mutex::new(pin),
+        })
+    }
+}
+
+impl Actuator for GpioActuator {
+    fn set(&self, on: bool) -> Result<(), GError> {
+        let mut pin = self.pin.lock().unwrap();
+        if on {
+            pin.set_low();
+        } else {
+            pin.set_high();
+        }
+        Ok(())
+    }
+
+    fn reported_state(&self) -> Option<bool> {
+        Some(self.pin.lock().unwrap().is_set_low())
+    }
+}
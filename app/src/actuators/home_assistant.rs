@@ -0,0 +1,340 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use error_stack::{Report, Result, ResultExt};
+use serde_json::{json, Value};
+use ureq::{Agent, AgentBuilder};
+
+use super::Actuator;
+use crate::{
+    config::{Action, HomeAssistantConfig},
+    GError,
+};
+
+/// Percent a brightness gesture changes the level by.
+const BRIGHTNESS_STEP: i32 = 10;
+
+/// Turns a home assistant entity on and off through its services.
+#[derive(Debug)]
+pub struct HomeAssistantActuator {
+    agent: Agent,
+    auth: String,
+    base: String,
+    entity_id: String,
+    domain: String,
+    /// Url and body of the service calls turning the entity on and off.
+    on: (String, String),
+    off: (String, String),
+    retries: u32,
+    timeout: Duration,
+}
+
+impl HomeAssistantActuator {
+    pub fn new(config: HomeAssistantConfig) -> Result<Self, GError> {
+        let (domain, _) = config
+            .entity_id
+            .split_once('.')
+            .ok_or(GError::ConfigError)
+            .attach_printable_lazy(|| {
+                format!("{} is not a home assistant entity id", config.entity_id)
+            })?;
+        let on_service = config
+            .service
+            .clone()
+            .unwrap_or_else(|| format!("{}.turn_on", domain));
+        let off_service = format!("{}.turn_off", domain);
+
+        let base = config.url.trim_end_matches('/');
+        let service_url = |service: &str| {
+            service
+                .split_once('.')
+                .map(|(domain, service)| format!("{}/api/services/{}/{}", base, domain, service))
+                .ok_or(GError::ConfigError)
+                .attach_printable_lazy(|| format!("{} is not a home assistant service", service))
+        };
+
+        let entity_id = Value::String(config.entity_id.clone());
+        let mut on_data = config.data;
+        on_data.insert("entity_id".into(), entity_id.clone());
+        let off_data = json!({ "entity_id": entity_id });
+
+        Ok(Self {
+            agent: AgentBuilder::new().build(),
+            auth: format!("Bearer {}", config.token),
+            base: base.to_owned(),
+            domain: domain.to_owned(),
+            entity_id: config.entity_id,
+            on: (
+                service_url(&on_service)?,
+                Value::Object(on_data).to_string(),
+            ),
+            off: (service_url(&off_service)?, off_data.to_string()),
+            retries: config.retries,
+            timeout: Duration::from_millis(config.timeout),
+        })
+    }
+
+    /// Service and data field setting the level of the entity in percent.
+    fn level_service(&self) -> Result<(&'static str, &'static str), GError> {
+        match self.domain.as_str() {
+            "light" => Ok(("turn_on", "brightness_pct")),
+            "fan" => Ok(("set_percentage", "percentage")),
+            "cover" => Ok(("set_cover_position", "position")),
+            _ => Err(GError::ConfigError)
+                .attach_printable(format!("{} has no level to set", self.entity_id)),
+        }
+    }
+
+    /// Service and data changing the level of the entity by `step` percent.
+    fn step_service(&self, step: i32) -> Result<(String, Value), GError> {
+        let (service, data) = match self.domain.as_str() {
+            "light" => ("turn_on", json!({ "brightness_step_pct": step })),
+            "fan" if step > 0 => ("increase_speed", json!({ "percentage_step": step })),
+            "fan" => ("decrease_speed", json!({ "percentage_step": -step })),
+            _ => {
+                return Err(GError::ConfigError)
+                    .attach_printable(format!("{} has no level to step", self.entity_id))
+            }
+        };
+
+        Ok((format!("{}/{}", self.domain, service), data))
+    }
+
+    /// Posts to a service, retrying failures until the retries or the timeout run out.
+    fn call(&self, (url, body): &(String, String)) -> Result<(), GError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut attempt = 0;
+
+        loop {
+            let res = self
+                .agent
+                .post(url)
+                .timeout(deadline.saturating_duration_since(Instant::now()))
+                .set("Authorization", &self.auth)
+                .set("Content-Type", "application/json")
+                .send_string(body);
+
+            let e = match res {
+                Ok(_) => return Ok(()),
+                // a bad token or service won't get better by asking again
+                Err(ureq::Error::Status(code, _)) if code < 500 => {
+                    return Err(GError::ActuatorError)
+                        .attach_printable(format!("{} answered {}", url, code))
+                }
+                Err(ureq::Error::Status(code, _)) => format!("{} answered {}", url, code),
+                Err(e) => format!("Couldn't reach {}: {}", url, e),
+            };
+
+            attempt += 1;
+            let backoff = Duration::from_millis(200 * attempt as u64);
+            if attempt > self.retries || Instant::now() + backoff >= deadline {
+                return Err(Report::new(GError::ActuatorError).attach_printable(e));
+            }
+            thread::sleep(backoff);
+        }
+    }
+}
+
+impl Actuator for HomeAssistantActuator {
+    fn set(&self, on: bool) -> Result<(), GError> {
+        self.call(if on { &self.on } else { &self.off })
+    }
+
+    fn set_level(&self, level: f32) -> Result<(), GError> {
+        let pct = (level.clamp(0.0, 1.0) * 100.0).round() as u32;
+        let (service, field) = self.level_service()?;
+
+        let mut data = json!({ "entity_id": self.entity_id });
+        data[field] = pct.into();
+        self.call(&(
+            format!("{}/api/services/{}/{}", self.base, self.domain, service),
+            data.to_string(),
+        ))
+    }
+
+    fn perform(&self, action: Action) -> Result<(), GError> {
+        let (service, mut data) = match action {
+            Action::On => return self.set(true),
+            Action::Off => return self.set(false),
+            Action::Toggle => ("homeassistant/toggle".into(), json!({})),
+            Action::BrightnessUp => self.step_service(BRIGHTNESS_STEP)?,
+            Action::BrightnessDown => self.step_service(-BRIGHTNESS_STEP)?,
+            Action::Next => ("media_player/media_next_track".into(), json!({})),
+            Action::Previous => ("media_player/media_previous_track".into(), json!({})),
+            Action::Level => {
+                return Err(GError::ActuatorError)
+                    .attach_printable("Levels are set through set_level")
+            }
+        };
+        data["entity_id"] = Value::String(self.entity_id.clone());
+
+        self.call(&(
+            format!("{}/api/services/{}", self.base, service),
+            data.to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Answers each request with the next status, returns the requests it got.
+    fn mock_server(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut requests = vec![];
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                let mut request = String::new();
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        len = value.trim().parse().unwrap();
+                    }
+                    request += &line;
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                request += &String::from_utf8(body).unwrap();
+                requests.push(request);
+
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} Mock\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]",
+                    status
+                )
+                .unwrap();
+            }
+            requests
+        });
+
+        (url, handle)
+    }
+
+    fn config(url: String) -> HomeAssistantConfig {
+        HomeAssistantConfig {
+            url,
+            token: "secret".into(),
+            entity_id: "light.bulb_1".into(),
+            service: None,
+            data: Default::default(),
+            retries: 2,
+            timeout: 1000,
+        }
+    }
+
+    #[test]
+    fn call_service_after_retry() {
+        let (url, server) = mock_server(vec![502, 200]);
+        let mut config = config(url);
+        config.service = Some("light.turn_on".into());
+        config.data.insert("brightness".into(), 255.into());
+
+        HomeAssistantActuator::new(config)
+            .unwrap()
+            .set(true)
+            .unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("POST /api/services/light/turn_on "));
+        assert!(requests[1].contains("Authorization: Bearer secret\r\n"));
+        assert!(requests[1].ends_with(r#"{"brightness":255,"entity_id":"light.bulb_1"}"#));
+    }
+
+    #[test]
+    fn no_retry_on_rejection() {
+        let (url, server) = mock_server(vec![401]);
+
+        let res = HomeAssistantActuator::new(config(url)).unwrap().set(false);
+
+        assert!(res.is_err());
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /api/services/light/turn_off "));
+        assert!(requests[0].ends_with(r#"{"entity_id":"light.bulb_1"}"#));
+    }
+
+    #[test]
+    fn step_level_of_domain() {
+        let (url, server) = mock_server(vec![200, 200]);
+        let mut fan = config(url.clone());
+        fan.entity_id = "fan.ceiling".into();
+        let fan = HomeAssistantActuator::new(fan).unwrap();
+        fan.perform(Action::BrightnessUp).unwrap();
+        fan.perform(Action::BrightnessDown).unwrap();
+
+        let mut config = config(url);
+        config.entity_id = "cover.blinds".into();
+        let err = HomeAssistantActuator::new(config)
+            .unwrap()
+            .perform(Action::BrightnessUp)
+            .unwrap_err();
+        assert!(matches!(err.current_context(), GError::ConfigError));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /api/services/fan/increase_speed "));
+        assert!(requests[0].ends_with(r#"{"entity_id":"fan.ceiling","percentage_step":10}"#));
+        assert!(requests[1].starts_with("POST /api/services/fan/decrease_speed "));
+        assert!(requests[1].ends_with(r#"{"entity_id":"fan.ceiling","percentage_step":10}"#));
+    }
+
+    #[test]
+    fn retry_within_timeout() {
+        let (url, server) = mock_server(vec![502, 502]);
+        let mut config = config(url);
+        config.retries = 10;
+        config.timeout = 500;
+
+        let start = Instant::now();
+        let res = HomeAssistantActuator::new(config).unwrap().set(true);
+
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_millis(500));
+        // 200ms after the first, the next backoff of 400ms would overrun
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn set_level_of_domain() {
+        let (url, server) = mock_server(vec![200, 200]);
+        for entity_id in ["fan.ceiling", "cover.blinds"] {
+            let mut config = config(url.clone());
+            config.entity_id = entity_id.into();
+            HomeAssistantActuator::new(config)
+                .unwrap()
+                .set_level(0.4)
+                .unwrap();
+        }
+
+        let mut config = config(url);
+        config.entity_id = "switch.heater".into();
+        let err = HomeAssistantActuator::new(config)
+            .unwrap()
+            .set_level(0.4)
+            .unwrap_err();
+        assert!(matches!(err.current_context(), GError::ConfigError));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /api/services/fan/set_percentage "));
+        assert!(requests[0].ends_with(r#"{"entity_id":"fan.ceiling","percentage":40}"#));
+        assert!(requests[1].starts_with("POST /api/services/cover/set_cover_position "));
+        assert!(requests[1].ends_with(r#"{"entity_id":"cover.blinds","position":40}"#));
+    }
+}
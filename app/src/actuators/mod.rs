@@ -0,0 +1,102 @@
+//! What switches a device once a gesture is aimed at it.
+
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use error_stack::{Result, ResultExt};
+
+use crate::{
+    config::{Action, ActuatorConfig, Device},
+    GError,
+};
+
+mod gpio;
+mod home_assistant;
+mod mqtt;
+mod network;
+mod pwm;
+mod simulated;
+
+pub use gpio::GpioActuator;
+pub use home_assistant::HomeAssistantActuator;
+pub use mqtt::MqttActuator;
+pub use network::NetworkActuator;
+pub use pwm::PwmActuator;
+pub use simulated::SimulatedActuator;
+
+pub trait Actuator: Send + Sync + fmt::Debug {
+    /// Switches the device on or off, doing nothing if it already is.
+    fn set(&self, on: bool) -> Result<(), GError>;
+
+    /// Sets a level between 0 and 1, for devices that aren't just on or off.
+    fn set_level(&self, level: f32) -> Result<(), GError> {
+        Err(GError::ActuatorError)
+            .attach_printable(format!("{:?} has no levels, got {}", self, level))
+    }
+
+    /// Runs an action other than switching on and off, like dimming.
+    fn perform(&self, action: Action) -> Result<(), GError> {
+        Err(GError::ActuatorError).attach_printable(format!("{:?} can't {:?}", self, action))
+    }
+
+    /// State the device itself reports, for devices that can tell.
+    fn reported_state(&self) -> Option<bool> {
+        None
+    }
+}
+
+/// The actuator of every device, opened once at startup and keyed by device
+/// name, so every action on a device goes through the same connection.
+#[derive(Clone, Debug, Default)]
+pub struct Actuators {
+    actuators: Arc<HashMap<String, Arc<dyn Actuator>>>,
+}
+
+impl Actuators {
+    /// A device whose actuator can't be opened is left out and can't be driven.
+    pub fn open(devices: &[Device]) -> Self {
+        let actuators = devices
+            .iter()
+            .filter_map(|device| match open(device) {
+                Ok(actuator) => Some((device.name.clone(), actuator)),
+                Err(e) => {
+                    println!("Couldn't open the actuator of {}: {:?}", device.name, e);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            actuators: Arc::new(actuators),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Actuator>, GError> {
+        self.actuators
+            .get(name)
+            .cloned()
+            .ok_or(GError::ActuatorError)
+            .attach_printable_lazy(|| format!("{} has no actuator, it failed to open", name))
+    }
+}
+
+pub fn open(device: &Device) -> Result<Arc<dyn Actuator>, GError> {
+    let config = device
+        .actuator_config()
+        .ok_or(GError::ConfigError)
+        .attach_printable_lazy(|| {
+            format!("Device {} has neither a pin nor an actuator", device.name)
+        })?;
+
+    Ok(match config {
+        ActuatorConfig::Gpio { pin } => Arc::new(GpioActuator::open(pin)?),
+        ActuatorConfig::Pwm { pin, frequency } => Arc::new(PwmActuator::open(pin, frequency)?),
+        ActuatorConfig::Simulated => Arc::new(SimulatedActuator::new(&device.name)),
+        ActuatorConfig::Network { address, timeout } => Arc::new(NetworkActuator::new(
+            &device.name,
+            address,
+            Duration::from_millis(timeout),
+        )),
+        ActuatorConfig::Mqtt(mqtt) => Arc::new(MqttActuator::connect(&device.name, &mqtt)?),
+        ActuatorConfig::HomeAssistant(ha) => Arc::new(HomeAssistantActuator::new(ha)?),
+    })
+}
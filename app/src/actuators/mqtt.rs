@@ -0,0 +1,202 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use error_stack::{Result, ResultExt};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use serde_json::Value;
+
+use super::Actuator;
+use crate::{config::MqttConfig, GError};
+
+/// Publishes the configured payloads to the command topic of the device, and
+/// keeps the last payload seen on its state topic.
+pub struct MqttActuator {
+    client: Client,
+    topic: String,
+    payload_on: String,
+    payload_off: String,
+    level_topic: Option<String>,
+    level_max: u32,
+    state: Arc<Mutex<Option<String>>>,
+}
+
+impl MqttActuator {
+    /// Starts the connection in the background, it keeps reconnecting until the broker is up.
+    pub fn connect(name: &str, config: &MqttConfig) -> Result<Self, GError> {
+        let client_id: String = format!("gesture-ease-{}", name)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        let mut options = MqttOptions::new(client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(5));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            options.set_credentials(username, password);
+        }
+
+        let (client, mut connection) = Client::new(options, 10);
+
+        let subscriber = client.clone();
+        let state_topic = config.state_topic.clone();
+        let state = Arc::new(Mutex::new(None));
+        let reported = state.clone();
+        let name = name.to_owned();
+        thread::spawn(move || {
+            for event in connection.iter() {
+                match event {
+                    // a clean session drops subscriptions, so renew it on every connect
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        if let Some(topic) = &state_topic {
+                            if let Err(e) = subscriber.try_subscribe(topic, QoS::AtMostOnce) {
+                                println!("Couldn't subscribe {} to {}: {:?}", name, topic, e);
+                            }
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+                        println!("{} reported {}", name, payload);
+                        *reported.lock().unwrap() = Some(payload);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("MQTT connection of {} failed: {:?}", name, e);
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            client,
+            topic: config.topic.clone(),
+            payload_on: config.payload_on.clone(),
+            payload_off: config.payload_off.clone(),
+            level_topic: config.level_topic.clone(),
+            level_max: config.level_max,
+            state,
+        })
+    }
+
+    /// Last payload the device reported on its state topic.
+    pub fn state(&self) -> Option<String> {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+impl fmt::Debug for MqttActuator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttActuator")
+            .field("topic", &self.topic)
+            .field("payload_on", &self.payload_on)
+            .field("payload_off", &self.payload_off)
+            .finish()
+    }
+}
+
+impl Actuator for MqttActuator {
+    fn set(&self, on: bool) -> Result<(), GError> {
+        let payload = if on {
+            &self.payload_on
+        } else {
+            &self.payload_off
+        };
+
+        // don't block the main loop while the broker is away
+        self.client
+            .try_publish(&self.topic, QoS::AtLeastOnce, false, payload.as_bytes())
+            .change_context(GError::ActuatorError)
+            .attach_printable_lazy(|| format!("Couldn't publish to {}", self.topic))
+    }
+
+    fn set_level(&self, level: f32) -> Result<(), GError> {
+        let topic = self
+            .level_topic
+            .as_ref()
+            .ok_or(GError::ActuatorError)
+            .attach_printable_lazy(|| format!("No level_topic next to {}", self.topic))?;
+        let payload = (level.clamp(0.0, 1.0) * self.level_max as f32).round() as u32;
+
+        self.client
+            .try_publish(topic, QoS::AtLeastOnce, false, payload.to_string())
+            .change_context(GError::ActuatorError)
+            .attach_printable_lazy(|| format!("Couldn't publish to {}", topic))
+    }
+
+    fn reported_state(&self) -> Option<bool> {
+        let state = self.state()?;
+        let state = match serde_json::from_str::<Value>(&state) {
+            Ok(Value::Object(json)) => json.get("state")?.as_str()?.to_owned(),
+            _ => state,
+        };
+
+        if state.eq_ignore_ascii_case(&self.payload_on) {
+            Some(true)
+        } else if state.eq_ignore_ascii_case(&self.payload_off) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    #[ignore = "needs an mqtt broker like mosquitto on localhost:1883"]
+    fn publish_to_broker() {
+        let config = MqttConfig {
+            host: "localhost".into(),
+            port: 1883,
+            topic: "gesture-ease/test/set".into(),
+            payload_on: "ON".into(),
+            payload_off: "OFF".into(),
+            state_topic: Some("gesture-ease/test/state".into()),
+            level_topic: None,
+            level_max: 100,
+            username: None,
+            password: None,
+        };
+
+        let (listener, mut connection) =
+            Client::new(MqttOptions::new("gesture-ease-test", "localhost", 1883), 10);
+        listener.subscribe(&config.topic, QoS::AtLeastOnce).unwrap();
+        let actuator = MqttActuator::connect("Test Lamp", &config).unwrap();
+
+        let start = Instant::now();
+        let mut received = None;
+        for event in connection.iter() {
+            match event.unwrap() {
+                Event::Incoming(Packet::SubAck(_)) => {
+                    actuator.set(true).unwrap();
+                    listener
+                        .publish(
+                            "gesture-ease/test/state",
+                            QoS::AtLeastOnce,
+                            false,
+                            r#"{"state": "OFF"}"#,
+                        )
+                        .unwrap();
+                }
+                Event::Incoming(Packet::Publish(publish)) => {
+                    received = Some(publish.payload);
+                    break;
+                }
+                _ => assert!(start.elapsed() < Duration::from_secs(5)),
+            }
+        }
+        assert_eq!(received.as_deref(), Some(&b"ON"[..]));
+
+        while actuator.state().is_none() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(actuator.reported_state(), Some(false));
+    }
+}
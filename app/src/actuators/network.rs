@@ -0,0 +1,118 @@
+use std::{
+    io::Write,
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use error_stack::{Result, ResultExt};
+use serde::Serialize;
+
+use super::Actuator;
+use crate::{config::Action, GError};
+
+#[derive(Serialize)]
+struct Command<'a> {
+    device: &'a str,
+    action: Action,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    level: Option<f32>,
+}
+
+/// Sends every action as a json line over a new tcp connection, for devices
+/// driven by another machine.
+#[derive(Debug)]
+pub struct NetworkActuator {
+    name: String,
+    address: String,
+    timeout: Duration,
+}
+
+impl NetworkActuator {
+    pub fn new(name: &str, address: String, timeout: Duration) -> Self {
+        Self {
+            name: name.to_owned(),
+            address,
+            timeout,
+        }
+    }
+
+    fn send(&self, action: Action, level: Option<f32>) -> Result<(), GError> {
+        let addr = self
+            .address
+            .to_socket_addrs()
+            .change_context(GError::ActuatorError)?
+            .next()
+            .ok_or(GError::ActuatorError)
+            .attach_printable_lazy(|| format!("Couldn't resolve {}", self.address))?;
+
+        let mut msg = serde_json::to_vec(&Command {
+            device: &self.name,
+            action,
+            level,
+        })
+        .change_context(GError::ActuatorError)?;
+        msg.push(b'\n');
+
+        TcpStream::connect_timeout(&addr, self.timeout)
+            .and_then(|mut stream| {
+                stream.set_write_timeout(Some(self.timeout))?;
+                stream.write_all(&msg)
+            })
+            .change_context(GError::ActuatorError)
+            .attach_printable_lazy(|| format!("Couldn't reach {} at {}", self.name, self.address))
+    }
+}
+
+impl Actuator for NetworkActuator {
+    fn set(&self, on: bool) -> Result<(), GError> {
+        self.send(if on { Action::On } else { Action::Off }, None)
+    }
+
+    fn set_level(&self, level: f32) -> Result<(), GError> {
+        self.send(Action::Level, Some(level))
+    }
+
+    fn perform(&self, action: Action) -> Result<(), GError> {
+        self.send(action, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[test]
+    fn send_action() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let actuator = NetworkActuator::new(
+            "Bulb_1",
+            listener.local_addr().unwrap().to_string(),
+            Duration::from_secs(1),
+        );
+
+        actuator.set(true).unwrap();
+        actuator.perform(Action::BrightnessDown).unwrap();
+        actuator.set_level(0.25).unwrap();
+
+        let mut lines = vec![];
+        for _ in 0..3 {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            lines.push(line);
+        }
+        assert_eq!(
+            lines,
+            [
+                "{\"device\":\"Bulb_1\",\"action\":\"on\"}\n",
+                "{\"device\":\"Bulb_1\",\"action\":\"brightness_down\"}\n",
+                "{\"device\":\"Bulb_1\",\"action\":\"level\",\"level\":0.25}\n"
+            ]
+        );
+    }
+}
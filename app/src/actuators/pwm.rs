@@ -0,0 +1,64 @@
+use std::sync::Mutex;
+
+use error_stack::{Result, ResultExt};
+use rppal::gpio::{Gpio, OutputPin};
+
+use super::Actuator;
+use crate::GError;
+
+/// A dimmer or mosfet on a gpio pin driven by software pwm, the duty cycle is the level.
+#[derive(Debug)]
+pub struct PwmActuator {
+    pin: Mutex<OutputPin>,
+    frequency: f64,
+    /// Current level and the last one above zero, restored when switched on.
+    level: Mutex<(f32, f32)>,
+}
+
+impl PwmActuator {
+    pub fn open(number: u8, frequency: f64) -> Result<Self, GError> {
+        let mut pin = Gpio::new()
+            .and_then(|gpio| gpio.get(number))
+            .change_context(GError::ActuatorError)
+            .attach_printable_lazy(|| format!("Couldn't open gpio pin {}", number))?
+            .into_output_low();
+        pin.set_reset_on_drop(false);
+
+        Ok(Self {
+            pin: Mutex::new(pin),
+            frequency,
+            level: Mutex::new((0.0, 1.0)),
+        })
+    }
+}
+
+impl Actuator for PwmActuator {
+    fn set(&self, on: bool) -> Result<(), GError> {
+        let last = self.level.lock().unwrap().1;
+        self.set_level(if on { last } else { 0.0 })
+    }
+
+    fn set_level(&self, level: f32) -> Result<(), GError> {
+        let level = level.clamp(0.0, 1.0);
+        let mut pin = self.pin.lock().unwrap();
+        if level == 0.0 {
+            pin.clear_pwm().change_context(GError::ActuatorError)?;
+            pin.set_low();
+        } else {
+            pin.set_pwm_frequency(self.frequency, level as f64)
+                .change_context(GError::ActuatorError)
+                .attach_printable_lazy(|| format!("Couldn't set pwm to {}", level))?;
+        }
+
+        let mut state = self.level.lock().unwrap();
+        state.0 = level;
+        if level > 0.0 {
+            state.1 = level;
+        }
+        Ok(())
+    }
+
+    fn reported_state(&self) -> Option<bool> {
+        Some(self.level.lock().unwrap().0 > 0.0)
+    }
+}
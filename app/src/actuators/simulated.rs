@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use error_stack::Result;
+
+use super::Actuator;
+use crate::{config::Action, GError};
+
+/// Keeps the state in memory and logs every change.
+#[derive(Debug)]
+pub struct SimulatedActuator {
+    name: String,
+    on: AtomicBool,
+}
+
+impl SimulatedActuator {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            on: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on.load(Ordering::Relaxed)
+    }
+}
+
+impl Actuator for SimulatedActuator {
+    fn set(&self, on: bool) -> Result<(), GError> {
+        if self.on.swap(on, Ordering::Relaxed) != on {
+            println!("{} turned {}", self.name, if on { "on" } else { "off" });
+        }
+        Ok(())
+    }
+
+    fn set_level(&self, level: f32) -> Result<(), GError> {
+        self.on.store(level > 0.0, Ordering::Relaxed);
+        println!("{} set to {:.0}%", self.name, level * 100.0);
+        Ok(())
+    }
+
+    fn perform(&self, action: Action) -> Result<(), GError> {
+        println!("{} got {:?}", self.name, action);
+        Ok(())
+    }
+
+    fn reported_state(&self) -> Option<bool> {
+        Some(self.is_on())
+    }
+}
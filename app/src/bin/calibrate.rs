@@ -0,0 +1,34 @@
+//! Calibrates the cameras from checkerboard corners and prints the config
+//! sections, `calibrate [corners.json] [calibration.toml]`.
+
+use std::fs;
+
+use gesture_ease::calibration::{Detections, StereoCalibration};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let corners = args.next().unwrap_or_else(|| "corners.json".to_owned());
+    let output = args.next();
+
+    let detections = Detections::open(&corners).unwrap();
+    let calibration = StereoCalibration::solve(&detections).unwrap();
+
+    eprintln!(
+        "Camera 1 reprojection error: {:.3}px",
+        calibration.camera1.rms
+    );
+    eprintln!(
+        "Camera 2 reprojection error: {:.3}px",
+        calibration.camera2.rms
+    );
+    eprintln!("Stereo reprojection error: {:.3}px", calibration.rms);
+
+    let sections = calibration.to_toml().unwrap();
+    match output {
+        Some(output) => {
+            fs::write(&output, sections).unwrap();
+            eprintln!("Wrote the camera sections to {}", output);
+        }
+        None => print!("{}", sections),
+    }
+}
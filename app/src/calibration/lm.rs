@@ -0,0 +1,73 @@
+use nalgebra::{DMatrix, DVector};
+
+const MAX_ITERATIONS: usize = 200;
+
+/// Finds the parameters with the least sum of squared residuals with
+/// Levenberg-Marquardt, taking the jacobian by finite differences.
+pub fn fit(
+    mut params: DVector<f64>,
+    residuals: &impl Fn(&DVector<f64>) -> DVector<f64>,
+) -> DVector<f64> {
+    let mut r = residuals(&params);
+    let mut cost = r.norm_squared();
+    let mut lambda = 1e-3;
+
+    for _ in 0..MAX_ITERATIONS {
+        let j = jacobian(&params, &r, residuals);
+        let jtj = j.transpose() * &j;
+        let jtr = j.transpose() * &r;
+
+        // grow the damping until a step lowers the cost
+        loop {
+            if lambda > 1e12 {
+                return params;
+            }
+            let mut a = jtj.clone();
+            for i in 0..a.nrows() {
+                a[(i, i)] += lambda * jtj[(i, i)].max(1e-12);
+            }
+            let step = match a.cholesky() {
+                Some(cholesky) => -cholesky.solve(&jtr),
+                None => {
+                    lambda *= 10.0;
+                    continue;
+                }
+            };
+
+            let next = &params + step;
+            let next_r = residuals(&next);
+            let next_cost = next_r.norm_squared();
+            if next_cost < cost {
+                let converged = cost - next_cost < 1e-12 * cost.max(1e-12);
+                params = next;
+                r = next_r;
+                cost = next_cost;
+                lambda = (lambda / 10.0).max(1e-12);
+                if converged {
+                    return params;
+                }
+                break;
+            }
+
+            lambda *= 10.0;
+        }
+    }
+
+    params
+}
+
+fn jacobian(
+    params: &DVector<f64>,
+    r: &DVector<f64>,
+    residuals: &impl Fn(&DVector<f64>) -> DVector<f64>,
+) -> DMatrix<f64> {
+    let mut j = DMatrix::zeros(r.len(), params.len());
+    let mut shifted = params.clone();
+    for col in 0..params.len() {
+        let h = 1e-7 * params[col].abs().max(1.0);
+        shifted[col] += h;
+        j.set_column(col, &((residuals(&shifted) - r) / h));
+        shifted[col] = params[col];
+    }
+    j
+}
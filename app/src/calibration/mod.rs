@@ -0,0 +1,472 @@
+//! Calibrates both cameras from checkerboard corners found in pictures taken
+//! by both at once, like `StereoCalibration/stereo_calibration.py` does with
+//! OpenCV.
+
+use std::{fs, path::Path};
+
+use error_stack::{Result, ResultExt};
+use glam::{EulerRot, Mat3, Quat};
+use nalgebra::{DVector, Matrix3, Rotation3, Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Distortion,
+    math::{essential_matrix, to_rows},
+    GError,
+};
+
+mod lm;
+mod zhang;
+
+/// Fitted per camera, `[fx, fy, cx, cy, k1, k2, p1, p2, k3]`.
+const INTRINSICS: usize = 9;
+/// Fitted per board, its rotation vector and translation.
+const POSE: usize = 6;
+
+/// Checkerboard corners in the order `cv.findChessboardCorners` gives them,
+/// along the first row of the board first.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Detections {
+    /// Inner corners along a row and along a column of the board.
+    pub columns: usize,
+    pub rows: usize,
+    /// Side of a square, the cameras are placed in the same unit.
+    pub square: f64,
+    pub img_width: u32,
+    pub img_height: u32,
+    pub views: Vec<View>,
+}
+
+/// Corners both cameras found in one picture each.
+#[derive(Deserialize, Debug, Clone)]
+pub struct View {
+    pub camera1: Vec<[f64; 2]>,
+    pub camera2: Vec<[f64; 2]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CameraCalibration {
+    pub intrinsics: Matrix3<f64>,
+    pub distortion: Distortion,
+    /// Root mean square distance in pixels between the corners found and
+    /// where the calibration puts them.
+    pub rms: f64,
+}
+
+/// Both cameras, with camera 2 placed relative to camera 1 like
+/// `cv.stereoCalibrate` does.
+#[derive(Debug, Clone)]
+pub struct StereoCalibration {
+    pub camera1: CameraCalibration,
+    pub camera2: CameraCalibration,
+    /// Takes points from camera 1 to camera 2 along with `translation`.
+    pub rotation: Matrix3<f64>,
+    pub translation: Vector3<f64>,
+    /// Root mean square reprojection error over both cameras, with the
+    /// intrinsics fixed.
+    pub rms: f64,
+    img_width: u32,
+    img_height: u32,
+}
+
+impl Detections {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, GError> {
+        let detections: Self = serde_json::from_str(
+            &fs::read_to_string(path)
+                .change_context(GError::ConfigError)
+                .attach_printable("Couldn't read the checkerboard corners")?,
+        )
+        .change_context(GError::ConfigError)?;
+        detections.check()?;
+        Ok(detections)
+    }
+
+    fn check(&self) -> Result<(), GError> {
+        if self.views.len() < 3 {
+            return Err(GError::ConfigError).attach_printable(format!(
+                "Calibrating takes at least 3 views of the board, got {}",
+                self.views.len()
+            ));
+        }
+
+        let corners = self.columns * self.rows;
+        for (i, view) in self.views.iter().enumerate() {
+            for (camera, found) in [(1, &view.camera1), (2, &view.camera2)] {
+                if found.len() != corners {
+                    return Err(GError::ConfigError).attach_printable(format!(
+                        "View {} has {} corners from camera {}, the board has {}",
+                        i,
+                        found.len(),
+                        camera,
+                        corners
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Corners on the board, which lies in the `z = 0` plane.
+    fn board(&self) -> Vec<Vector3<f64>> {
+        (0..self.rows)
+            .flat_map(|row| (0..self.columns).map(move |col| (row, col)))
+            .map(|(row, col)| Vector3::new(col as f64, row as f64, 0.0) * self.square)
+            .collect()
+    }
+
+    fn corners(&self, camera: impl Fn(&View) -> &Vec<[f64; 2]>) -> Vec<Vec<Vector2<f64>>> {
+        self.views
+            .iter()
+            .map(|view| {
+                camera(view)
+                    .iter()
+                    .map(|[x, y]| Vector2::new(*x, *y))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl StereoCalibration {
+    /// Calibrates each camera on its own, then finds where camera 2 is with
+    /// the intrinsics fixed.
+    pub fn solve(detections: &Detections) -> Result<Self, GError> {
+        detections.check()?;
+        let board = detections.board();
+        let corners1 = detections.corners(|view| &view.camera1);
+        let corners2 = detections.corners(|view| &view.camera2);
+
+        let (camera1, params1) =
+            calibrate_camera(&board, &corners1).attach_printable("Couldn't calibrate camera 1")?;
+        let (camera2, params2) =
+            calibrate_camera(&board, &corners2).attach_printable("Couldn't calibrate camera 2")?;
+        let (intrinsics1, poses1) = params1.split_at(INTRINSICS);
+        let (intrinsics2, poses2) = params2.split_at(INTRINSICS);
+
+        // start from camera 2 where the boards put it on average
+        let mut start = [0.0; POSE];
+        for (pose1, pose2) in poses1.chunks(POSE).zip(poses2.chunks(POSE)) {
+            let relative = compose(pose2, &invert(pose1));
+            for (sum, x) in start.iter_mut().zip(relative) {
+                *sum += x / detections.views.len() as f64;
+            }
+        }
+        let params = DVector::from_iterator(
+            POSE + poses1.len(),
+            start.iter().chain(poses1.iter()).copied(),
+        );
+
+        let residuals = |params: &DVector<f64>| {
+            let (stereo, poses) = params.as_slice().split_at(POSE);
+            let mut r = Vec::with_capacity(4 * board.len() * corners1.len());
+            for ((pose1, found1), found2) in poses.chunks(POSE).zip(&corners1).zip(&corners2) {
+                let pose2 = compose(stereo, pose1);
+                reprojection(intrinsics1, pose1, &board, found1, &mut r);
+                reprojection(intrinsics2, &pose2, &board, found2, &mut r);
+            }
+            DVector::from_vec(r)
+        };
+        let params = lm::fit(params, &residuals);
+        let rms = rms(&residuals(&params));
+
+        let stereo = &params.as_slice()[..POSE];
+        Ok(Self {
+            camera1,
+            camera2,
+            rotation: rotation(stereo).into_inner(),
+            translation: Vector3::new(stereo[3], stereo[4], stereo[5]),
+            rms,
+            img_width: detections.img_width,
+            img_height: detections.img_height,
+        })
+    }
+
+    pub fn essential_matrix(&self) -> Matrix3<f64> {
+        essential_matrix(&self.rotation, &self.translation)
+    }
+
+    /// `[camera1]`, `[camera2]` and `[stereo]` sections for the config, with
+    /// camera 1 at the origin looking along x.
+    pub fn to_toml(&self) -> Result<String, GError> {
+        // camera 2 in camera 1's frame, which looks along z with y down
+        let center = -(self.rotation.transpose() * self.translation);
+        let orientation =
+            optical_to_world() * self.rotation.transpose() * optical_to_world().transpose();
+        let orientation = Mat3::from_cols_slice(orientation.map(|x| x as f32).as_slice());
+        let (yaw, pitch, roll) = Quat::from_mat3(&orientation).to_euler(EulerRot::ZYX);
+
+        let sections = Sections {
+            camera1: self.section(
+                &self.camera1,
+                Vector3::zeros(),
+                (0.0, 0.0, 0.0),
+                Matrix3::identity(),
+                Vector3::zeros(),
+            ),
+            camera2: self.section(
+                &self.camera2,
+                optical_to_world() * center,
+                (yaw, pitch, roll),
+                self.rotation,
+                self.translation,
+            ),
+            stereo: StereoSection {
+                essential_matrix: to_rows(&self.essential_matrix()),
+            },
+        };
+
+        toml::to_string(&sections)
+            .change_context(GError::ConfigError)
+            .attach_printable("Couldn't write the calibration")
+    }
+
+    fn section(
+        &self,
+        camera: &CameraCalibration,
+        pos: Vector3<f64>,
+        (yaw, pitch, roll): (f32, f32, f32),
+        rotation: Matrix3<f64>,
+        translation: Vector3<f64>,
+    ) -> CameraSection {
+        let k = camera.intrinsics;
+        CameraSection {
+            fov_x: (2.0 * (self.img_width as f64 / (2.0 * k[(0, 0)])).atan()) as f32,
+            fov_y: (2.0 * (self.img_height as f64 / (2.0 * k[(1, 1)])).atan()) as f32,
+            pos_x: pos.x as f32,
+            pos_y: pos.y as f32,
+            pos_z: pos.z as f32,
+            pitch,
+            yaw,
+            roll,
+            img_height: self.img_height,
+            img_width: self.img_width,
+            intrensic_prams: to_rows(&k),
+            rotation_matrix: to_rows(&rotation),
+            translation_vector: translation.into(),
+            distortion: camera.distortion,
+        }
+    }
+}
+
+/// Takes camera coordinates, x to the right of the image, y down and z along
+/// the optical axis, to the world, which has cameras looking along x with z up.
+fn optical_to_world() -> Matrix3<f64> {
+    #[rustfmt::skip]
+    let m = Matrix3::new(
+        0.0, 0.0, 1.0,
+        1.0, 0.0, 0.0,
+        0.0, -1.0, 0.0,
+    );
+    m
+}
+
+#[derive(Serialize)]
+struct Sections {
+    camera1: CameraSection,
+    camera2: CameraSection,
+    stereo: StereoSection,
+}
+
+#[derive(Serialize)]
+struct CameraSection {
+    fov_x: f32,
+    fov_y: f32,
+    pos_x: f32,
+    pos_y: f32,
+    pos_z: f32,
+    pitch: f32,
+    yaw: f32,
+    roll: f32,
+    img_height: u32,
+    img_width: u32,
+    intrensic_prams: [[f64; 3]; 3],
+    rotation_matrix: [[f64; 3]; 3],
+    translation_vector: [f64; 3],
+    distortion: Distortion,
+}
+
+#[derive(Serialize)]
+struct StereoSection {
+    essential_matrix: [[f64; 3]; 3],
+}
+
+/// Fits the intrinsics, distortion and the pose of every board for one
+/// camera. Returns the fitted parameters, the intrinsics followed by the poses.
+fn calibrate_camera(
+    board: &[Vector3<f64>],
+    corners: &[Vec<Vector2<f64>>],
+) -> Result<(CameraCalibration, Vec<f64>), GError> {
+    let homographies = corners
+        .iter()
+        .map(|found| zhang::homography(board, found))
+        .collect::<Result<Vec<_>, GError>>()?;
+    let k = zhang::intrinsics(&homographies)?;
+
+    let mut params = vec![k[(0, 0)], k[(1, 1)], k[(0, 2)], k[(1, 2)]];
+    params.resize(INTRINSICS, 0.0);
+    for h in &homographies {
+        let (r, t) = zhang::pose(&k, h)?;
+        params.extend(r.scaled_axis().iter());
+        params.extend(t.iter());
+    }
+
+    let residuals = |params: &DVector<f64>| {
+        let (intrinsics, poses) = params.as_slice().split_at(INTRINSICS);
+        let mut r = Vec::with_capacity(2 * board.len() * corners.len());
+        for (pose, found) in poses.chunks(POSE).zip(corners) {
+            reprojection(intrinsics, pose, board, found, &mut r);
+        }
+        DVector::from_vec(r)
+    };
+    let params = lm::fit(DVector::from_vec(params), &residuals);
+    let rms = rms(&residuals(&params));
+
+    let intrinsics = &params.as_slice()[..INTRINSICS];
+    let (fx, fy, cx, cy) = (intrinsics[0], intrinsics[1], intrinsics[2], intrinsics[3]);
+    let calibration = CameraCalibration {
+        intrinsics: Matrix3::new(fx, 0.0, cx, 0.0, fy, cy, 0.0, 0.0, 1.0),
+        distortion: distortion(intrinsics),
+        rms,
+    };
+    Ok((calibration, params.as_slice().to_vec()))
+}
+
+/// Pushes the distance from each corner found to where the board is projected.
+fn reprojection(
+    intrinsics: &[f64],
+    pose: &[f64],
+    board: &[Vector3<f64>],
+    found: &[Vector2<f64>],
+    r: &mut Vec<f64>,
+) {
+    let (rotation, translation) = (rotation(pose), Vector3::new(pose[3], pose[4], pose[5]));
+    let distortion = distortion(intrinsics);
+    for (point, found) in board.iter().zip(found) {
+        let p = rotation * point + translation;
+        let (x, y) = distortion.distort(p.x / p.z, p.y / p.z);
+        r.push(intrinsics[0] * x + intrinsics[2] - found.x);
+        r.push(intrinsics[1] * y + intrinsics[3] - found.y);
+    }
+}
+
+fn distortion(intrinsics: &[f64]) -> Distortion {
+    Distortion::BrownConrady {
+        k1: intrinsics[4],
+        k2: intrinsics[5],
+        p1: intrinsics[6],
+        p2: intrinsics[7],
+        k3: intrinsics[8],
+    }
+}
+
+fn rotation(pose: &[f64]) -> Rotation3<f64> {
+    Rotation3::from_scaled_axis(Vector3::new(pose[0], pose[1], pose[2]))
+}
+
+/// Pose doing `b` and then `a`.
+fn compose(a: &[f64], b: &[f64]) -> [f64; POSE] {
+    let r = rotation(a) * rotation(b);
+    let t = rotation(a) * Vector3::new(b[3], b[4], b[5]) + Vector3::new(a[3], a[4], a[5]);
+    let r = r.scaled_axis();
+    [r.x, r.y, r.z, t.x, t.y, t.z]
+}
+
+fn invert(pose: &[f64]) -> [f64; POSE] {
+    let r = rotation(pose).inverse();
+    let t = -(r * Vector3::new(pose[3], pose[4], pose[5]));
+    let r = r.scaled_axis();
+    [r.x, r.y, r.z, t.x, t.y, t.z]
+}
+
+/// Root mean square distance of corners, whose residuals come in x and y pairs.
+fn rms(r: &DVector<f64>) -> f64 {
+    (r.norm_squared() / (r.len() / 2).max(1) as f64).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CameraProperties, StereoConfig};
+
+    #[test]
+    fn calibrate_synthetic_views() {
+        let k1 = Matrix3::new(800.0, 0.0, 330.0, 0.0, 790.0, 250.0, 0.0, 0.0, 1.0);
+        let k2 = Matrix3::new(760.0, 0.0, 310.0, 0.0, 765.0, 235.0, 0.0, 0.0, 1.0);
+        let distortion1 = distortion(&[0.0, 0.0, 0.0, 0.0, -0.12, 0.05, 0.001, -0.0005, 0.0]);
+        let distortion2 = distortion(&[0.0, 0.0, 0.0, 0.0, 0.08, -0.03, -0.0008, 0.0006, 0.0]);
+        // camera 2 ten units to the right of camera 1 and turned a little
+        let stereo = [0.01, -0.08, 0.005, -10.0, 0.3, 0.5];
+
+        let board: Vec<_> = (0..5)
+            .flat_map(|row| (0..7).map(move |col| (row, col)))
+            .map(|(row, col)| Vector3::new(col as f64, row as f64, 0.0) * 2.5)
+            .collect();
+        let project = |k: &Matrix3<f64>, distortion: &Distortion, pose: &[f64]| {
+            board
+                .iter()
+                .map(|point| {
+                    let p = rotation(pose) * point + Vector3::new(pose[3], pose[4], pose[5]);
+                    let (x, y) = distortion.distort(p.x / p.z, p.y / p.z);
+                    [k[(0, 0)] * x + k[(0, 2)], k[(1, 1)] * y + k[(1, 2)]]
+                })
+                .collect()
+        };
+
+        let poses = [
+            [0.3, 0.0, 0.0, -8.0, -5.0, 55.0],
+            [-0.3, 0.1, 0.0, -7.0, -6.0, 60.0],
+            [0.0, 0.35, 0.1, -9.0, -4.0, 58.0],
+            [0.1, -0.35, -0.1, -6.0, -5.0, 65.0],
+            [0.25, 0.25, 0.2, -8.0, -7.0, 62.0],
+            [-0.2, -0.2, 0.0, -7.5, -4.5, 50.0],
+        ];
+        let views = poses
+            .iter()
+            .map(|pose| View {
+                camera1: project(&k1, &distortion1, pose),
+                camera2: project(&k2, &distortion2, &compose(&stereo, pose)),
+            })
+            .collect();
+        let detections = Detections {
+            columns: 7,
+            rows: 5,
+            square: 2.5,
+            img_width: 640,
+            img_height: 480,
+            views,
+        };
+
+        let calibration = StereoCalibration::solve(&detections).unwrap();
+        assert!(calibration.rms < 1e-3);
+        assert!((calibration.camera1.intrinsics - k1).abs().max() < 0.1);
+        assert!((calibration.camera2.intrinsics - k2).abs().max() < 0.1);
+        assert!(
+            (calibration.rotation - rotation(&stereo).into_inner())
+                .abs()
+                .max()
+                < 1e-4
+        );
+        assert!(
+            (calibration.translation - Vector3::new(-10.0, 0.3, 0.5))
+                .abs()
+                .max()
+                < 1e-3
+        );
+
+        // the sections can be pasted into the config as they are
+        #[derive(Deserialize)]
+        struct Sections {
+            camera1: CameraProperties,
+            camera2: CameraProperties,
+            stereo: StereoConfig,
+        }
+        let sections: Sections = toml::from_str(&calibration.to_toml().unwrap()).unwrap();
+        assert_eq!(sections.camera1.pos_y, 0.0);
+        assert!((sections.camera2.pos_y - 10.0).abs() < 0.5);
+        assert!(sections.camera2.distortion.is_some());
+        assert!(sections
+            .stereo
+            .fundamental(&sections.camera1, &sections.camera2)
+            .is_some());
+    }
+}
@@ -0,0 +1,129 @@
+//! Closed form first guesses from Zhang's method, which the refinement starts from.
+
+use error_stack::{Result, ResultExt};
+use nalgebra::{DMatrix, DVector, Matrix3, Rotation3, SymmetricEigen, Vector2, Vector3};
+
+use crate::GError;
+
+/// Homography taking the board plane to the image.
+pub fn homography(
+    board: &[Vector3<f64>],
+    corners: &[Vector2<f64>],
+) -> Result<Matrix3<f64>, GError> {
+    let (board_norm, board_points) = normalize(board.iter().map(|p| p.xy()));
+    let (image_norm, image_points) = normalize(corners.iter().copied());
+
+    let mut a = DMatrix::zeros(2 * board.len(), 9);
+    for (i, (b, c)) in board_points.iter().zip(&image_points).enumerate() {
+        let (x, y, u, v) = (b.x, b.y, c.x, c.y);
+        let rows = [
+            [-x, -y, -1.0, 0.0, 0.0, 0.0, u * x, u * y, u],
+            [0.0, 0.0, 0.0, -x, -y, -1.0, v * x, v * y, v],
+        ];
+        for (k, row) in rows.iter().enumerate() {
+            for (col, value) in row.iter().enumerate() {
+                a[(2 * i + k, col)] = *value;
+            }
+        }
+    }
+
+    let h = null_vector(a)?;
+    let h = Matrix3::from_row_slice(h.as_slice());
+    let image_inv = image_norm.try_inverse().ok_or(GError::MathError)?;
+
+    Ok(image_inv * h * board_norm)
+}
+
+/// Intrinsics without skew from the homographies of at least three views.
+pub fn intrinsics(homographies: &[Matrix3<f64>]) -> Result<Matrix3<f64>, GError> {
+    let v = |h: &Matrix3<f64>, i: usize, j: usize| {
+        [
+            h[(0, i)] * h[(0, j)],
+            h[(0, i)] * h[(1, j)] + h[(1, i)] * h[(0, j)],
+            h[(1, i)] * h[(1, j)],
+            h[(2, i)] * h[(0, j)] + h[(0, i)] * h[(2, j)],
+            h[(2, i)] * h[(1, j)] + h[(1, i)] * h[(2, j)],
+            h[(2, i)] * h[(2, j)],
+        ]
+    };
+
+    let mut a = DMatrix::zeros(2 * homographies.len(), 6);
+    for (i, h) in homographies.iter().enumerate() {
+        let (v12, v11, v22) = (v(h, 0, 1), v(h, 0, 0), v(h, 1, 1));
+        for col in 0..6 {
+            a[(2 * i, col)] = v12[col];
+            a[(2 * i + 1, col)] = v11[col] - v22[col];
+        }
+    }
+
+    // b = [B11, B12, B22, B13, B23, B33] of B = K^-T K^-1, up to scale
+    let mut b = null_vector(a)?;
+    if b[0] < 0.0 {
+        b = -b;
+    }
+    let (b11, b12, b22, b13, b23, b33) = (b[0], b[1], b[2], b[3], b[4], b[5]);
+
+    let d = b11 * b22 - b12 * b12;
+    let cy = (b12 * b13 - b11 * b23) / d;
+    let lambda = b33 - (b13 * b13 + cy * (b12 * b13 - b11 * b23)) / b11;
+    let fx = (lambda / b11).sqrt();
+    let fy = (lambda * b11 / d).sqrt();
+    let cx = -b13 * fx * fx / lambda;
+
+    if !(fx.is_finite() && fy.is_finite() && cx.is_finite() && cy.is_finite()) {
+        return Err(GError::MathError)
+            .attach_printable("The views don't constrain the intrinsics, tilt the board more");
+    }
+
+    Ok(Matrix3::new(fx, 0.0, cx, 0.0, fy, cy, 0.0, 0.0, 1.0))
+}
+
+/// Rotation and translation of the board for a view.
+pub fn pose(k: &Matrix3<f64>, h: &Matrix3<f64>) -> Result<(Rotation3<f64>, Vector3<f64>), GError> {
+    let a = k.try_inverse().ok_or(GError::MathError)? * h;
+    let mut scale = 1.0 / a.column(0).norm();
+    // the board is in front of the camera
+    if a[(2, 2)] * scale < 0.0 {
+        scale = -scale;
+    }
+
+    let r1 = a.column(0) * scale;
+    let r2 = a.column(1) * scale;
+    let r = Matrix3::from_columns(&[r1, r2, r1.cross(&r2)]);
+    let t = a.column(2) * scale;
+
+    // closest proper rotation
+    let svd = r.svd(true, true);
+    let (u, v_t) = match (svd.u, svd.v_t) {
+        (Some(u), Some(v_t)) => (u, v_t),
+        _ => return Err(GError::MathError).attach_printable("SVD didn't converge"),
+    };
+    let r = Rotation3::from_matrix_unchecked(u * v_t);
+
+    Ok((r, t))
+}
+
+/// Moves points to their centroid and scales them to an average distance of √2.
+fn normalize(points: impl Iterator<Item = Vector2<f64>>) -> (Matrix3<f64>, Vec<Vector2<f64>>) {
+    let points: Vec<_> = points.collect();
+    let n = points.len() as f64;
+    let mean = points.iter().sum::<Vector2<f64>>() / n;
+    let spread = points.iter().map(|p| (p - mean).norm()).sum::<f64>() / n;
+    let s = std::f64::consts::SQRT_2 / spread.max(f64::EPSILON);
+
+    let t = Matrix3::new(s, 0.0, -s * mean.x, 0.0, s, -s * mean.y, 0.0, 0.0, 1.0);
+    let points = points.iter().map(|p| (p - mean) * s).collect();
+    (t, points)
+}
+
+/// Unit vector `x` with the least `|Ax|`.
+fn null_vector(a: DMatrix<f64>) -> Result<DVector<f64>, GError> {
+    let ata = a.transpose() * a;
+    let eigen = SymmetricEigen::new(ata);
+    let smallest = eigen.eigenvalues.imin();
+    let x = eigen.eigenvectors.column(smallest).into_owned();
+    if !x.iter().all(|x| x.is_finite()) {
+        return Err(GError::MathError).attach_printable("Eigen decomposition didn't converge");
+    }
+    Ok(x)
+}
@@ -0,0 +1,223 @@
+use crate::protocol::{FrameId, Header, MessageKind, PixelFormat};
+use crate::traits::{FrameSource, GenProcess, Reconnect, Responder, WantIpc};
+use crate::GError;
+use error_stack::{Report, Result};
+use flume::unbounded;
+use flume::{Receiver, Sender};
+use serde::Deserialize;
+use std::{
+    os::unix::net::UnixStream,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+mod replay;
+
+pub use replay::ReplaySource;
+
+#[derive(Clone)]
+pub struct CameraProc {
+    data_sender: Sender<FrameId>,
+    data_receiver: Receiver<FrameId>,
+    response_sender: Sender<(FrameId, Result<Frames, GError>)>,
+    response_receiver: Receiver<(FrameId, Result<Frames, GError>)>,
+    stream_sender: Sender<UnixStream>,
+    stream_receiver: Receiver<UnixStream>,
+    w1: u32,
+    w2: u32,
+    h1: u32,
+    h2: u32,
+    unix_stream: Arc<UnixStream>,
+    live: Arc<AtomicBool>,
+    next_frame: Arc<AtomicU32>,
+}
+
+impl CameraProc {
+    pub fn new(unix_stream: UnixStream, w1: u32, h1: u32, w2: u32, h2: u32) -> Self {
+        let (data_sender, data_receiver) = unbounded();
+        let (response_sender, response_receiver) = unbounded();
+        let (stream_sender, stream_receiver) = unbounded();
+        let unix_stream = Arc::new(unix_stream);
+
+        Self {
+            data_sender,
+            data_receiver,
+            w1,
+            w2,
+            h1,
+            h2,
+            response_sender,
+            response_receiver,
+            stream_sender,
+            stream_receiver,
+            unix_stream,
+            live: Arc::new(AtomicBool::new(true)),
+            next_frame: Arc::new(AtomicU32::new(1)),
+        }
+    }
+
+    /// Sends the image dimensions of both cameras, has to be done on every new connection.
+    fn configure(&self) -> Result<(), GError> {
+        // the sequence id of a configure message is the camera number
+        self.send_frame(
+            &Header::new(MessageKind::Configure, 1, 0).with_image(
+                PixelFormat::Rgb888,
+                self.w1,
+                self.h1,
+            ),
+            &[],
+        )?;
+        self.send_frame(
+            &Header::new(MessageKind::Configure, 2, 0).with_image(
+                PixelFormat::Rgb888,
+                self.w2,
+                self.h2,
+            ),
+            &[],
+        )
+    }
+
+    fn capture(&self, seq: FrameId) -> Result<Frames, GError> {
+        self.send_signal(MessageKind::Capture, seq)?;
+        let img1 = self.recv_ipc(MessageKind::Image, seq)?;
+        let img2 = self.recv_ipc(MessageKind::Image, seq)?;
+
+        Ok(Frames {
+            id: seq,
+            cam1: img1,
+            cam2: img2,
+        })
+    }
+
+    pub fn run(&self) -> JoinHandle<()> {
+        let mut instance = self.clone();
+        println!("Camera process connected");
+
+        if let Err(e) = instance.configure() {
+            println!("Camera process disconnected: {:?}", e);
+            instance.mark_down();
+        }
+
+        thread::spawn(move || loop {
+            let frame = instance.recv_data().unwrap();
+            // only the newest capture is still waited for
+            let frame = instance
+                .data_receiver()
+                .try_iter()
+                .fold(frame, |skipped, newer| {
+                    let err = Report::new(GError::Timeout)
+                        .attach_printable(format!("Skipped for frame {}", newer));
+                    instance.send_error(skipped, err).unwrap();
+                    newer
+                });
+
+            if instance.reconnect() {
+                println!("Camera process reconnected");
+                if let Err(e) = instance.configure() {
+                    println!("Camera process disconnected: {:?}", e);
+                    instance.mark_down();
+                }
+            }
+            if !instance.is_up() {
+                instance
+                    .send_error(
+                        frame,
+                        Report::new(GError::WorkerDown)
+                            .attach_printable("Camera process is not connected"),
+                    )
+                    .unwrap();
+                continue;
+            }
+
+            match instance.capture(frame) {
+                Ok(res) => instance.send_response(frame, res).unwrap(),
+                Err(e) => {
+                    println!("Camera process disconnected");
+                    instance.mark_down();
+                    instance.send_error(frame, e).unwrap();
+                }
+            }
+        })
+    }
+
+    fn next_frame(&self) -> FrameId {
+        self.next_frame.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn get(&self) -> Result<Frames, GError> {
+        let frame = self.next_frame();
+        self.send_data(frame)?;
+        self.recv_response(frame)
+    }
+
+    pub fn get_timeout(&self, timeout: Duration) -> Result<Frames, GError> {
+        let frame = self.next_frame();
+        self.send_data(frame)?;
+        self.recv_response_timeout(frame, timeout)
+    }
+}
+
+impl FrameSource for CameraProc {
+    fn next_frames(&mut self, timeout: Duration) -> Result<Frames, GError> {
+        self.get_timeout(timeout)
+    }
+}
+
+impl GenProcess for CameraProc {
+    type Send = FrameId;
+
+    fn data_sender(&self) -> &Sender<Self::Send> {
+        &self.data_sender
+    }
+
+    fn data_receiver(&self) -> &Receiver<Self::Send> {
+        &self.data_receiver
+    }
+}
+
+impl Responder for CameraProc {
+    type Response = Frames;
+
+    fn response_sender(&self) -> &Sender<(FrameId, Result<Self::Response, GError>)> {
+        &self.response_sender
+    }
+
+    fn response_receiver(&self) -> &Receiver<(FrameId, Result<Self::Response, GError>)> {
+        &self.response_receiver
+    }
+}
+
+impl WantIpc for CameraProc {
+    fn unix_stream(&self) -> &UnixStream {
+        &self.unix_stream
+    }
+}
+
+impl Reconnect for CameraProc {
+    fn stream_sender(&self) -> &Sender<UnixStream> {
+        &self.stream_sender
+    }
+
+    fn stream_receiver(&self) -> &Receiver<UnixStream> {
+        &self.stream_receiver
+    }
+
+    fn liveness(&self) -> &AtomicBool {
+        &self.live
+    }
+
+    fn set_unix_stream(&mut self, stream: UnixStream) {
+        self.unix_stream = Arc::new(stream);
+    }
+}
+
+#[derive(Default, Debug, Deserialize)]
+pub struct Frames {
+    pub id: FrameId,
+    pub cam1: Vec<u8>,
+    pub cam2: Vec<u8>,
+}
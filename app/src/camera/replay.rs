@@ -0,0 +1,347 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use error_stack::{Report, Result, ResultExt};
+
+use super::Frames;
+use crate::{config::ReplayConfig, protocol::FrameId, traits::FrameSource, GError};
+
+const CAMERAS: [&str; 2] = ["cam1", "cam2"];
+
+enum Location {
+    File(PathBuf),
+    /// Offset and length of the entry data in the archive.
+    Entry(u64, u64),
+}
+
+struct Recorded {
+    name: String,
+    location: Location,
+}
+
+/// Plays back stereo pairs recorded to disk, see [`ReplayConfig`] for the layout.
+pub struct ReplaySource {
+    archive: Option<File>,
+    pairs: Vec<(Recorded, Recorded)>,
+    w1: u32,
+    h1: u32,
+    w2: u32,
+    h2: u32,
+    interval: Duration,
+    repeat: bool,
+    next: usize,
+    next_frame: FrameId,
+    due: Option<Instant>,
+}
+
+impl ReplaySource {
+    pub fn open(config: &ReplayConfig, w1: u32, h1: u32, w2: u32, h2: u32) -> Result<Self, GError> {
+        let interval = match Duration::try_from_secs_f32(1.0 / config.fps) {
+            Ok(interval) if config.fps.is_finite() && config.fps > 0.0 => interval,
+            _ => {
+                return Err(GError::ConfigError).attach_printable(format!(
+                    "Replay rate has to be a positive number, got {}",
+                    config.fps
+                ))
+            }
+        };
+
+        let (archive, [cam1, cam2]) = if config.path.is_dir() {
+            (None, list_dir(&config.path)?)
+        } else {
+            let archive = File::open(&config.path)
+                .change_context(GError::CameraError)
+                .attach_printable_lazy(|| format!("Couldn't open {}", config.path.display()))?;
+            let recorded = list_archive(&archive)?;
+            (Some(archive), recorded)
+        };
+
+        let mut cam2 = cam2;
+        let pairs: Vec<_> = cam1
+            .into_iter()
+            .filter_map(|(stem, img1)| match cam2.remove(&stem) {
+                Some(img2) => Some((img1, img2)),
+                None => {
+                    println!("Skipping {} without a matching cam2 frame", img1.name);
+                    None
+                }
+            })
+            .collect();
+
+        if pairs.is_empty() {
+            return Err(GError::CameraError).attach_printable(format!(
+                "No recorded pairs found in {}",
+                config.path.display()
+            ));
+        }
+        println!(
+            "Replaying {} pairs from {}",
+            pairs.len(),
+            config.path.display()
+        );
+
+        Ok(Self {
+            archive,
+            pairs,
+            w1,
+            h1,
+            w2,
+            h2,
+            interval,
+            repeat: config.repeat,
+            next: 0,
+            next_frame: 1,
+            due: None,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    fn read(&self, recorded: &Recorded) -> Result<Vec<u8>, GError> {
+        let bytes = match recorded.location {
+            Location::File(ref path) => fs::read(path).change_context(GError::CameraError),
+            Location::Entry(offset, len) => {
+                let mut archive = self
+                    .archive
+                    .as_ref()
+                    .expect("archive entries are only listed for an archive");
+                let mut bytes = vec![0; len as usize];
+                archive
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| archive.read_exact(&mut bytes))
+                    .change_context(GError::CameraError)
+                    .map(|_| bytes)
+            }
+        };
+
+        bytes.attach_printable_lazy(|| format!("Couldn't read {}", recorded.name))
+    }
+}
+
+impl FrameSource for ReplaySource {
+    fn next_frames(&mut self, _timeout: Duration) -> Result<Frames, GError> {
+        if self.next == self.pairs.len() {
+            if !self.repeat {
+                return Err(Report::new(GError::ReplayEnded));
+            }
+            self.next = 0;
+        }
+
+        // hold the configured rate, like a camera would
+        if let Some(due) = self.due {
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+        self.due = Some(Instant::now() + self.interval);
+
+        let (img1, img2) = &self.pairs[self.next];
+        let cam1 = decode(&img1.name, self.read(img1)?, self.w1, self.h1)?;
+        let cam2 = decode(&img2.name, self.read(img2)?, self.w2, self.h2)?;
+
+        self.next += 1;
+        let id = self.next_frame;
+        self.next_frame += 1;
+
+        Ok(Frames { id, cam1, cam2 })
+    }
+}
+
+fn extension(name: &str) -> Option<String> {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+}
+
+fn is_frame(name: &str) -> bool {
+    matches!(
+        extension(name).as_deref(),
+        Some("rgb" | "raw" | "png" | "jpg" | "jpeg")
+    )
+}
+
+/// Recorded images of each camera by file stem.
+type Listing = [BTreeMap<String, Recorded>; 2];
+
+fn list_dir(path: &Path) -> Result<Listing, GError> {
+    let mut listing = Listing::default();
+
+    for (cam, recorded) in CAMERAS.iter().zip(listing.iter_mut()) {
+        let dir = path.join(cam);
+        let entries = fs::read_dir(&dir)
+            .change_context(GError::CameraError)
+            .attach_printable_lazy(|| format!("Couldn't list {}", dir.display()))?;
+
+        for entry in entries {
+            let path = entry.change_context(GError::CameraError)?.path();
+            let name = path.to_string_lossy().into_owned();
+            if !path.is_file() || !is_frame(&name) {
+                continue;
+            }
+
+            let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+            recorded.insert(
+                stem,
+                Recorded {
+                    name,
+                    location: Location::File(path),
+                },
+            );
+        }
+    }
+
+    Ok(listing)
+}
+
+fn list_archive(archive: &File) -> Result<Listing, GError> {
+    let mut listing = Listing::default();
+    let mut tar = tar::Archive::new(archive);
+
+    for entry in tar.entries().change_context(GError::CameraError)? {
+        let entry = entry.change_context(GError::CameraError)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path().change_context(GError::CameraError)?;
+        let name = path.to_string_lossy().into_owned();
+        let cam = path
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .and_then(|dir| CAMERAS.iter().position(|cam| dir == *cam));
+        let cam = match cam {
+            Some(cam) if is_frame(&name) => cam,
+            _ => continue,
+        };
+
+        let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let location = Location::Entry(entry.raw_file_position(), entry.size());
+        listing[cam].insert(stem, Recorded { name, location });
+    }
+
+    Ok(listing)
+}
+
+/// Turns a recorded image into the raw rgb the models expect.
+fn decode(name: &str, bytes: Vec<u8>, w: u32, h: u32) -> Result<Vec<u8>, GError> {
+    let img = match extension(name).as_deref() {
+        Some("png" | "jpg" | "jpeg") => image::load_from_memory(&bytes)
+            .change_context(GError::CameraError)
+            .attach_printable_lazy(|| format!("Couldn't decode {}", name))?
+            .into_rgb8()
+            .into_raw(),
+        _ => bytes,
+    };
+
+    if img.len() != (w * h * 3) as usize {
+        return Err(GError::CameraError)
+            .attach_printable(format!("{} is not a {}x{} rgb image", name, w, h));
+    }
+
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("gesture-ease-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for cam in CAMERAS {
+            fs::create_dir_all(dir.join(cam)).unwrap();
+        }
+        dir
+    }
+
+    fn replay_config(path: PathBuf) -> ReplayConfig {
+        ReplayConfig {
+            path,
+            fps: 1000.0,
+            repeat: false,
+        }
+    }
+
+    #[test]
+    fn replay_dir() {
+        let dir = scratch_dir("replay-dir");
+        fs::write(dir.join("cam1/0002.rgb"), [2; 12]).unwrap();
+        fs::write(dir.join("cam1/0001.rgb"), [1; 12]).unwrap();
+        fs::write(dir.join("cam1/0003.rgb"), [3; 12]).unwrap();
+        fs::write(dir.join("cam2/0002.rgb"), [2; 12]).unwrap();
+        fs::write(dir.join("cam2/notes.txt"), "not a frame").unwrap();
+        image::RgbImage::from_raw(2, 2, vec![1; 12])
+            .unwrap()
+            .save(dir.join("cam2/0001.png"))
+            .unwrap();
+
+        let mut source = ReplaySource::open(&replay_config(dir.clone()), 2, 2, 2, 2).unwrap();
+        // 0003 has no cam2 frame
+        assert_eq!(source.len(), 2);
+
+        let first = source.next_frames(Duration::ZERO).unwrap();
+        assert_eq!(first.id, 1);
+        assert_eq!(first.cam1, vec![1; 12]);
+        assert_eq!(first.cam2, vec![1; 12]);
+        let second = source.next_frames(Duration::ZERO).unwrap();
+        assert_eq!(second.cam2, vec![2; 12]);
+
+        let end = source.next_frames(Duration::ZERO).unwrap_err();
+        assert!(matches!(end.current_context(), GError::ReplayEnded));
+
+        let mut config = replay_config(dir.clone());
+        for fps in [0.0, -1.0, f32::NAN, f32::INFINITY, f32::MIN_POSITIVE] {
+            config.fps = fps;
+            let err = ReplaySource::open(&config, 2, 2, 2, 2).err().unwrap();
+            assert!(matches!(err.current_context(), GError::ConfigError));
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replay_archive() {
+        let dir = scratch_dir("replay-archive");
+        let path = dir.join("session.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for (name, fill) in [("cam2/a.raw", 5), ("cam1/a.raw", 4), ("cam1/b.raw", 1)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(12);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, &[fill; 12][..])
+                .unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+
+        let mut config = replay_config(path);
+        config.repeat = true;
+        let mut source = ReplaySource::open(&config, 2, 2, 2, 2).unwrap();
+        assert_eq!(source.len(), 1);
+
+        for id in 1..=2 {
+            let frames = source.next_frames(Duration::ZERO).unwrap();
+            assert_eq!(frames.id, id);
+            assert_eq!(frames.cam1, vec![4; 12]);
+            assert_eq!(frames.cam2, vec![5; 12]);
+        }
+
+        let mut source = ReplaySource::open(&config, 3, 3, 2, 2).unwrap();
+        assert!(source.next_frames(Duration::ZERO).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
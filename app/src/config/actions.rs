@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::Gesture;
+
+/// What a gesture or sequence aimed at a device does to it, set per device as
+/// `actions = { swipe_right = "on", swipe_left = "off", double_toggle = "off" }`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Toggle,
+    On,
+    Off,
+    BrightnessUp,
+    BrightnessDown,
+    Next,
+    Previous,
+    /// Sets the level of the device from the value the gesture carries, like
+    /// how high the hand is.
+    Level,
+}
+
+/// Sets off an action, either a gesture or a sequence of them by its name.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum Trigger {
+    Gesture(Gesture),
+    Sequence(String),
+}
+
+impl Action {
+    /// Used by devices without their own `actions`.
+    pub fn for_gesture(gesture: &Gesture) -> Option<Self> {
+        Some(match gesture {
+            Gesture::Toggle => Self::Toggle,
+            Gesture::On => Self::On,
+            Gesture::Off => Self::Off,
+            Gesture::BrightnessUp => Self::BrightnessUp,
+            Gesture::BrightnessDown => Self::BrightnessDown,
+            Gesture::Next => Self::Next,
+            Gesture::Previous => Self::Previous,
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,109 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// What drives a device, set per device as `[devices.actuator]`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ActuatorConfig {
+    /// A relay on a raspberry pi gpio pin.
+    Gpio { pin: u8 },
+    /// A dimmable device on a gpio pin, driven by software pwm.
+    Pwm {
+        pin: u8,
+        /// Hz
+        #[serde(default = "default_frequency")]
+        frequency: f64,
+    },
+    /// Only logs and keeps the state in memory, for running off the pi.
+    Simulated,
+    /// Sends a json line per action to a tcp listener.
+    Network {
+        address: String,
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+    },
+    /// Publishes a command to a topic on an mqtt broker.
+    Mqtt(MqttConfig),
+    /// Calls the services of a home assistant entity over its rest api.
+    #[serde(rename = "homeassistant")]
+    HomeAssistant(HomeAssistantConfig),
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    /// Topic the command is published to.
+    pub topic: String,
+    #[serde(default = "default_payload_on")]
+    pub payload_on: String,
+    #[serde(default = "default_payload_off")]
+    pub payload_off: String,
+    /// Topic the device reports its state on, if any. The state is read from
+    /// payloads matching `payload_on` or `payload_off`, or from the `state`
+    /// field of a json payload.
+    #[serde(default)]
+    pub state_topic: Option<String>,
+    /// Topic levels are published to as a number from 0 to `level_max`,
+    /// for dimmers and the like.
+    #[serde(default)]
+    pub level_topic: Option<String>,
+    #[serde(default = "default_level_max")]
+    pub level_max: u32,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct HomeAssistantConfig {
+    /// Base url of the instance, like `http://homeassistant.local:8123`.
+    pub url: String,
+    /// Long-lived access token.
+    pub token: String,
+    pub entity_id: String,
+    /// Service that turns the entity on, like `scene.turn_on`, `<domain>.turn_on`
+    /// of the entity by default. It's turned off with `<domain>.turn_off`.
+    #[serde(default)]
+    pub service: Option<String>,
+    /// Extra service data sent when turning on, like `{ brightness = 255 }`.
+    #[serde(default)]
+    pub data: Map<String, Value>,
+    /// Attempts after the first one when home assistant can't be reached or fails.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Milliseconds a call may take, retries included.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+/// Milliseconds
+fn default_timeout() -> u64 {
+    1000
+}
+
+fn default_frequency() -> f64 {
+    200.0
+}
+
+fn default_level_max() -> u32 {
+    100
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_payload_on() -> String {
+    "ON".into()
+}
+
+fn default_payload_off() -> String {
+    "OFF".into()
+}
+
+fn default_retries() -> u32 {
+    2
+}
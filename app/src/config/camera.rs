@@ -2,9 +2,11 @@ use std::sync::OnceLock;
 
 // TODO: Vec3A or Vec3
 use glam::{EulerRot, Quat, Vec3A};
+use nalgebra::Vector3;
 use serde::Deserialize;
 
-use crate::{HasGlamPosition, HasGlamQuat};
+use super::Distortion;
+use crate::{math::matrix3, HasGlamPosition, HasGlamQuat, ImageCoords};
 
 #[derive(Deserialize, Debug)]
 pub struct CameraProperties {
@@ -18,8 +20,18 @@ pub struct CameraProperties {
     pub roll: f32,
     pub img_height: u32,
     pub img_width: u32,
+    /// Left out when read from the calibration file, or when not calibrated.
+    #[serde(default)]
     pub intrensic_prams: [[f64; 3]; 3],
+    #[serde(default = "identity")]
     pub rotation_matrix: [[f64; 3]; 3],
+    /// Takes points from the frame of the stereo calibration, which is
+    /// camera 1 looking along z, to this camera along with `rotation_matrix`.
+    #[serde(default)]
+    pub translation_vector: [f64; 3],
+    /// Left out for lenses close enough to an ideal pinhole.
+    #[serde(default)]
+    pub distortion: Option<Distortion>,
     #[serde(skip)]
     quat: OnceLock<Quat>,
     #[serde(skip)]
@@ -45,8 +57,10 @@ impl CameraProperties {
             pitch: 0.0,
             yaw: 0.0,
             roll: 0.0,
-            intrensic_prams: &sample_intrensic_matrix,
-            rotation_matrix: &sample_rotation_matrix,
+            intrensic_prams: sample_intrensic_matrix,
+            rotation_matrix: sample_rotation_matrix,
+            translation_vector: [0.0; 3],
+            distortion: None,
             img_height: 720,
             img_width: 1280,
             quat: OnceLock::new(),
@@ -55,6 +69,27 @@ impl CameraProperties {
         }
     }
 
+    /// Where the image coordinates would be without the lens distortion.
+    pub fn undistort(&self, coords: &ImageCoords) -> ImageCoords {
+        let (distortion, k_inv) = match (
+            &self.distortion,
+            matrix3(&self.intrensic_prams).try_inverse(),
+        ) {
+            (Some(distortion), Some(k_inv)) => (distortion, k_inv),
+            _ => return ImageCoords::new(coords.x, coords.y, self.img_width, self.img_height),
+        };
+
+        let k = self.intrensic_prams;
+        let normalized = k_inv * Vector3::new(coords.x as f64, coords.y as f64, 1.0);
+        let (x, y) = distortion.undistort(normalized.x, normalized.y);
+        ImageCoords::new(
+            (k[0][0] * x + k[0][1] * y + k[0][2]) as f32,
+            (k[1][1] * y + k[1][2]) as f32,
+            self.img_width,
+            self.img_height,
+        )
+    }
+
     pub fn direction_vector(&self) -> &Vec3A {
         self.dir_vec
             .get_or_init(|| self.quat().mul_vec3a(crate::math::BASE_FORWARD_VECTOR))
@@ -65,6 +100,10 @@ impl CameraProperties {
     }
 }
 
+fn identity() -> [[f64; 3]; 3] {
+    [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+}
+
 impl HasGlamPosition for CameraProperties {
     fn pos(&self) -> &Vec3A {
         self.pos
@@ -117,8 +156,10 @@ mod tests {
             pitch: 0.2,
             yaw: 0.69,
             roll: -0.69,
-            intrensic_prams: &sample_intrensic_matrix,
-            rotation_matrix: &sample_rotation_matrix,
+            intrensic_prams: sample_intrensic_matrix,
+            rotation_matrix: sample_rotation_matrix,
+            translation_vector: [0.0; 3],
+            distortion: None,
             img_height: 720,
             img_width: 1280,
             quat: OnceLock::new(),
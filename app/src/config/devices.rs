@@ -1,17 +1,27 @@
-use std::sync::OnceLock;
+use std::{collections::HashMap, sync::OnceLock};
 
 use glam::Vec3A;
-use rppal::gpio::{Gpio, OutputPin};
 use rust_3d::{BoundingBox3D, HasBoundingBox3D, HasBoundingBox3DMaybe, Point3D};
 use serde::Deserialize;
-use std::sync::{Arc, Mutex};
 
+use super::{Action, ActuatorConfig, Trigger};
 use crate::HasGlamPosition;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Device {
     pub name: String,
-    pub pin: u8,
+    /// Shorthand for a gpio actuator on this pin.
+    #[serde(default)]
+    pub pin: Option<u8>,
+    #[serde(default)]
+    pub actuator: Option<ActuatorConfig>,
+    /// State the device is put in at startup.
+    #[serde(default)]
+    pub initially_on: bool,
+    /// Gestures and sequences the device answers to, replacing the defaults of
+    /// [`Action::for_gesture`].
+    #[serde(default)]
+    pub actions: Option<HashMap<Trigger, Action>>,
     min_x: f32,
     min_y: f32,
     min_z: f32,
@@ -20,8 +30,6 @@ pub struct Device {
     max_z: f32,
     #[serde(skip)]
     pos: OnceLock<Vec3A>,
-    #[serde(skip)]
-    gpio: OnceLock<Arc<Mutex<OutputPin>>>,
 }
 
 impl Device {
@@ -35,14 +43,18 @@ impl Device {
         })
     }
 
-    pub fn get_gpio(&self) -> Arc<Mutex<OutputPin>> {
-        self.gpio
-            .get_or_init(|| {
-                let mut p = Gpio::new().unwrap().get(self.pin).unwrap().into_output();
-                p.set_low();
-                Arc::new(Mutex::new(p))
-            })
+    pub fn actuator_config(&self) -> Option<ActuatorConfig> {
+        self.actuator
             .clone()
+            .or(self.pin.map(|pin| ActuatorConfig::Gpio { pin }))
+    }
+
+    pub fn action(&self, trigger: &Trigger) -> Option<Action> {
+        match (&self.actions, trigger) {
+            (Some(actions), _) => actions.get(trigger).copied(),
+            (None, Trigger::Gesture(gesture)) => Action::for_gesture(gesture),
+            (None, Trigger::Sequence(_)) => None,
+        }
     }
 }
 
@@ -73,3 +85,47 @@ impl HasBoundingBox3D for Device {
         .unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Gesture;
+
+    #[test]
+    fn map_gestures_to_actions() {
+        let device = |actions: &str| -> Device {
+            toml::from_str(&format!(
+                r#"
+                name = "Fan"
+                min_x = 0
+                min_y = 0
+                min_z = 0
+                max_x = 1
+                max_y = 1
+                max_z = 1
+                {}"#,
+                actions
+            ))
+            .unwrap()
+        };
+
+        let gesture = Trigger::Gesture;
+        let double_toggle = Trigger::Sequence("double_toggle".into());
+
+        let lamp = device("");
+        assert_eq!(
+            lamp.action(&gesture(Gesture::BrightnessUp)),
+            Some(Action::BrightnessUp)
+        );
+        assert_eq!(lamp.action(&gesture(Gesture::Point)), None);
+        assert_eq!(lamp.action(&double_toggle), None);
+
+        let fan = device(
+            r#"actions = { swipe_right = "on", SwipeLeft = "off", double_toggle = "toggle" }"#,
+        );
+        assert_eq!(fan.action(&gesture(Gesture::SwipeRight)), Some(Action::On));
+        assert_eq!(fan.action(&gesture(Gesture::SwipeLeft)), Some(Action::Off));
+        assert_eq!(fan.action(&gesture(Gesture::Toggle)), None);
+        assert_eq!(fan.action(&double_toggle), Some(Action::Toggle));
+    }
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+/// Iterations taken to undo the distortion, as many as `cv::undistortPoints` takes.
+const ITERATIONS: usize = 20;
+
+/// Lens distortion with the coefficients OpenCV calibrates, working on
+/// normalized image coordinates, that is pixels taken through `K^-1`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum Distortion {
+    /// Radial and tangential distortion, `[k1, k2, p1, p2, k3]` from `cv::calibrateCamera`.
+    BrownConrady {
+        #[serde(default)]
+        k1: f64,
+        #[serde(default)]
+        k2: f64,
+        #[serde(default)]
+        p1: f64,
+        #[serde(default)]
+        p2: f64,
+        #[serde(default)]
+        k3: f64,
+    },
+    /// Equidistant fisheye distortion, `[k1, k2, k3, k4]` from `cv::fisheye::calibrate`.
+    Fisheye {
+        #[serde(default)]
+        k1: f64,
+        #[serde(default)]
+        k2: f64,
+        #[serde(default)]
+        k3: f64,
+        #[serde(default)]
+        k4: f64,
+    },
+}
+
+impl Distortion {
+    /// Where the lens puts a point of an ideal pinhole camera.
+    pub fn distort(&self, x: f64, y: f64) -> (f64, f64) {
+        match *self {
+            Self::BrownConrady { k1, k2, p1, p2, k3 } => {
+                let r2 = x * x + y * y;
+                let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+                (
+                    x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+                    y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+                )
+            }
+            Self::Fisheye { k1, k2, k3, k4 } => {
+                let r = x.hypot(y);
+                if r == 0.0 {
+                    return (x, y);
+                }
+                let theta = r.atan();
+                let t2 = theta * theta;
+                let theta_d = theta * (1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4))));
+                (x * theta_d / r, y * theta_d / r)
+            }
+        }
+    }
+
+    /// Where a point seen through the lens is for an ideal pinhole camera.
+    pub fn undistort(&self, x: f64, y: f64) -> (f64, f64) {
+        match *self {
+            Self::BrownConrady { k1, k2, p1, p2, k3 } => {
+                let (mut ux, mut uy) = (x, y);
+                for _ in 0..ITERATIONS {
+                    let r2 = ux * ux + uy * uy;
+                    let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+                    let dx = 2.0 * p1 * ux * uy + p2 * (r2 + 2.0 * ux * ux);
+                    let dy = p1 * (r2 + 2.0 * uy * uy) + 2.0 * p2 * ux * uy;
+                    ux = (x - dx) / radial;
+                    uy = (y - dy) / radial;
+                }
+                (ux, uy)
+            }
+            Self::Fisheye { k1, k2, k3, k4 } => {
+                let theta_d = x.hypot(y);
+                if theta_d == 0.0 {
+                    return (x, y);
+                }
+                // newton's method on theta_d = theta * (1 + k1 theta^2 + ... + k4 theta^8)
+                let mut theta = theta_d;
+                for _ in 0..ITERATIONS {
+                    let t2 = theta * theta;
+                    let f = theta * (1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4)))) - theta_d;
+                    let df =
+                        1.0 + t2 * (3.0 * k1 + t2 * (5.0 * k2 + t2 * (7.0 * k3 + t2 * 9.0 * k4)));
+                    theta -= f / df;
+                }
+                let scale = theta.tan() / theta_d;
+                (x * scale, y * scale)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_distortion() {
+        let models: Vec<Distortion> = [
+            "model = 'brown_conrady'\nk1 = -0.3\nk2 = 0.1\np1 = 0.001\np2 = -0.002\nk3 = -0.02",
+            "model = 'fisheye'\nk1 = 0.05\nk2 = -0.01\nk3 = 0.002",
+        ]
+        .iter()
+        .map(|model| toml::from_str(model).unwrap())
+        .collect();
+
+        for model in models {
+            for (x, y) in [(0.0, 0.0), (0.2, -0.1), (-0.4, 0.3), (0.5, 0.45)] {
+                let (dx, dy) = model.distort(x, y);
+                let (ux, uy) = model.undistort(dx, dy);
+                assert!(
+                    (ux - x).abs() < 1e-6 && (uy - y).abs() < 1e-6,
+                    "{model:?} at {x}, {y}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use serde::Deserialize;
+
+/// How steady a gesture has to be before it's acted on, times in milliseconds.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FilterConfig {
+    /// Frames of each person a gesture is voted over.
+    pub window: usize,
+    /// Frames in the window that have to show the gesture.
+    pub votes: usize,
+    /// How long the gesture has to keep winning the vote.
+    pub hold: u64,
+    /// Time after an action before the same device reacts again, gestures
+    /// setting a level aren't held back.
+    pub cooldown: u64,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            window: 3,
+            votes: 2,
+            hold: 0,
+            cooldown: 3000,
+        }
+    }
+}
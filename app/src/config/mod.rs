@@ -1,34 +1,140 @@
-use std::{fs, path::PathBuf, sync::OnceLock};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 use error_stack::{Report, ResultExt};
 use rust_3d::AABBTree3D;
 use serde::Deserialize;
 
+mod actions;
+mod actuators;
 mod camera;
 mod devices;
+mod distortion;
+mod filter;
+mod models;
+mod opencv;
+mod record;
+mod replay;
+mod sequences;
+mod stereo;
+mod timeouts;
+mod tracker;
+mod triangulation;
 
+pub use actions::{Action, Trigger};
+pub use actuators::{ActuatorConfig, HomeAssistantConfig, MqttConfig};
 pub use camera::CameraProperties;
 pub use devices::Device;
+pub use distortion::Distortion;
+pub use filter::FilterConfig;
+pub use models::{BackendConfig, ModelsConfig, OnnxConfig};
+pub use opencv::{Nodes, OpenCvCalibration};
+pub use record::RecordConfig;
+pub use replay::ReplayConfig;
+pub use sequences::{SequenceConfig, Step};
+pub use stereo::StereoConfig;
+pub use timeouts::Timeouts;
+pub use tracker::TrackerConfig;
+pub use triangulation::Triangulation;
 
-use crate::GError;
+use crate::{math::matrix3, models::Gesture, GError};
 
 #[derive(Deserialize)]
 pub struct Config {
     pub camera1: CameraProperties,
     pub camera2: CameraProperties,
+    /// Fills in the calibration of both cameras from a file OpenCV wrote.
+    #[serde(default)]
+    pub calibration: Option<OpenCvCalibration>,
+    #[serde(default)]
+    pub stereo: Option<StereoConfig>,
+    #[serde(default)]
+    pub triangulation: Triangulation,
     pub devices: Vec<Device>,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub models: ModelsConfig,
+    #[serde(default)]
+    pub replay: Option<ReplayConfig>,
+    #[serde(default)]
+    pub record: Option<RecordConfig>,
+    #[serde(default)]
+    pub tracker: TrackerConfig,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    #[serde(default)]
+    pub sequences: Vec<SequenceConfig>,
+    /// Where the on/off state and level of the devices is kept across restarts,
+    /// relative to the config file.
+    #[serde(default = "default_state_file")]
+    pub state_file: PathBuf,
     #[serde(skip)]
     aabbtree: OnceLock<AABBTree3D<Device>>,
 }
 
 impl Config {
     pub fn open(path: PathBuf) -> error_stack::Result<Self, GError> {
-        toml::from_str(
-            &fs::read_to_string(path)
+        let mut config: Self = toml::from_str(
+            &fs::read_to_string(&path)
                 .change_context(GError::ConfigError)
                 .attach_printable("Couldn't read the config file")?,
         )
-        .change_context(GError::ConfigError)
+        .change_context(GError::ConfigError)?;
+
+        // files are next to the config file rather than wherever we're started from
+        let dir = path.parent().unwrap_or(Path::new(""));
+        config.state_file = dir.join(&config.state_file);
+        if let Some(calibration) = &mut config.calibration {
+            calibration.path = dir.join(&calibration.path);
+            calibration.apply(&mut config.camera1, &mut config.camera2, &mut config.stereo)?;
+        }
+        if config.triangulation == Triangulation::Dlt {
+            for camera in [&config.camera1, &config.camera2] {
+                if matrix3(&camera.intrensic_prams).try_inverse().is_none() {
+                    return Err(GError::ConfigError).attach_printable(
+                        "Triangulating with dlt needs the intrensic_prams of both cameras",
+                    );
+                }
+            }
+        }
+
+        config.check_triggers()?;
+
+        Ok(config)
+    }
+
+    /// Every sequence a device answers to has to be configured. A sequence named
+    /// like a gesture would never be told apart from it.
+    fn check_triggers(&self) -> error_stack::Result<(), GError> {
+        for sequence in &self.sequences {
+            if sequence.name.parse::<Gesture>().is_ok() {
+                return Err(GError::ConfigError).attach_printable(format!(
+                    "Sequence {} has the name of a gesture",
+                    sequence.name
+                ));
+            }
+        }
+
+        for device in &self.devices {
+            for trigger in device.actions.iter().flat_map(|actions| actions.keys()) {
+                let name = match trigger {
+                    Trigger::Sequence(name) => name,
+                    Trigger::Gesture(_) => continue,
+                };
+                if !self.sequences.iter().any(|sequence| &sequence.name == name) {
+                    return Err(GError::ConfigError).attach_printable(format!(
+                        "{} of {} is neither a gesture nor one of the sequences",
+                        name, device.name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
     }
 
     pub fn aabbtree(&self) -> &AABBTree3D<Device> {
@@ -37,22 +143,21 @@ impl Config {
     }
 }
 
+fn default_state_file() -> PathBuf {
+    "device_state.json".into()
+}
+
 impl TryFrom<PathBuf> for Config {
     type Error = Report<GError>;
 
     fn try_from(value: PathBuf) -> Result<Self, Self::Error> {
-        toml::from_str(
-            &fs::read_to_string(value)
-                .change_context(GError::ConfigError)
-                .attach_printable("Couldn't read the config file")?,
-        )
-        .change_context(GError::ConfigError)
+        Self::open(value)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Config;
+    use super::*;
 
     #[test]
     fn parse_config() {
@@ -66,6 +171,10 @@ mod tests {
         pitch = -1
         yaw = -0.5
         roll = 0
+        img_height = 972
+        img_width = 1296
+        intrensic_prams = [[1425.4, 0, 725.5], [0, 1404.0, 400.3], [0, 0, 1]]
+        rotation_matrix = [[1, 0, 0], [0, 1, 0], [0, 0, 1]]
 
         [camera2]
         fov_x = 0.3
@@ -76,9 +185,14 @@ mod tests {
         pitch = -1
         yaw = 1
         roll = 0
+        img_height = 972
+        img_width = 1296
+        intrensic_prams = [[1425.4, 0, 725.5], [0, 1404.0, 400.3], [0, 0, 1]]
+        rotation_matrix = [[1, 0, 0], [0, 1, 0], [0, 0, 1]]
 
         [[devices]]
         name = "Fist of Family Values"
+        pin = 23
         min_x = -69
         min_y = -69
         min_z = -69
@@ -88,6 +202,7 @@ mod tests {
 
         [[devices]]
         name = "Distributor of Freedom"
+        pin = 27
         min_x = 0
         min_y = 0
         min_z = 0
@@ -99,4 +214,87 @@ mod tests {
 
         assert_eq!(config.devices.len(), 2);
     }
+
+    #[test]
+    fn check_sequence_names() {
+        let sequences = r#"
+            [[sequences]]
+            name = "double_toggle"
+            steps = ["toggle", "toggle"]
+
+            [[devices]]
+            name = "Dimmer"
+            min_x = 0
+            min_y = 0
+            min_z = 0
+            max_x = 1
+            max_y = 1
+            max_z = 1"#;
+        let config = |extra: &str| {
+            let config = format!("{}{}\n{}", crate::mock::CONFIG, sequences, extra);
+            toml::from_str::<Config>(&config).unwrap()
+        };
+
+        let known = config(r#"actions = { double_toggle = "off", swipe_left = "on" }"#);
+        known.check_triggers().unwrap();
+
+        let misspelled = config(r#"actions = { doubel_toggle = "off" }"#);
+        let err = misspelled.check_triggers().unwrap_err();
+        assert!(matches!(err.current_context(), GError::ConfigError));
+
+        let mut gesture = config("");
+        gesture.sequences[0].name = "Swipe_Left".into();
+        assert!(gesture.check_triggers().is_err());
+    }
+
+    #[test]
+    fn open_files_next_to_config() {
+        let dir = std::env::temp_dir().join(format!("gesture-ease-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let camera = |name: &str| {
+            format!(
+                r#"
+                [{}]
+                fov_x = 0.3
+                fov_y = 0.3
+                pos_x = 0
+                pos_y = 0
+                pos_z = 0
+                pitch = 0
+                yaw = 0
+                roll = 0
+                img_height = 972
+                img_width = 1296"#,
+                name
+            )
+        };
+        let config = format!(
+            "devices = []\n[calibration]\npath = \"stereo.json\"\n{}\n{}",
+            camera("camera1"),
+            camera("camera2")
+        );
+        fs::write(dir.join("config.toml"), config).unwrap();
+        let mat = |data: &str| {
+            format!(
+                r#"{{ "type_id": "opencv-matrix", "rows": 3, "cols": {}, "dt": "d", "data": [{}] }}"#,
+                data.split(',').count() / 3,
+                data
+            )
+        };
+        let k = mat("1400, 0, 700, 0, 1390, 410, 0, 0, 1");
+        let calibration = format!(
+            r#"{{ "CameraMatrixL": {}, "CameraMatrixR": {}, "RotationMatrix": {}, "TranslationMatrix": {} }}"#,
+            k,
+            k,
+            mat("1, 0, 0, 0, 1, 0, 0, 0, 1"),
+            mat("-23.8, 0, -0.3")
+        );
+        fs::write(dir.join("stereo.json"), calibration).unwrap();
+
+        let config = Config::open(dir.join("config.toml")).unwrap();
+        assert_eq!(config.camera2.translation_vector, [-23.8, 0.0, -0.3]);
+        assert_eq!(config.state_file, dir.join("device_state.json"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
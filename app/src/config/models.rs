@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// Where each model runs, by default all of them run in their own process.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ModelsConfig {
+    pub hpe: BackendConfig,
+    pub gesture: BackendConfig,
+    pub head: BackendConfig,
+}
+
+impl ModelsConfig {
+    /// Number of model processes expected to connect over the socket.
+    pub fn num_ipc(&self) -> usize {
+        [&self.hpe, &self.gesture, &self.head]
+            .iter()
+            .filter(|backend| matches!(backend, BackendConfig::Ipc))
+            .count()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum BackendConfig {
+    #[default]
+    Ipc,
+    Onnx(OnnxConfig),
+}
+
+/// An onnx model run in process. The model takes a single `1x3xHxW` rgb input
+/// scaled to `[0, 1]` and returns one row per person, starting with the
+/// normalized nose position.
+#[derive(Deserialize, Debug, Clone)]
+pub struct OnnxConfig {
+    pub path: PathBuf,
+    pub input_width: u32,
+    pub input_height: u32,
+    /// Rows scoring below this are dropped, or read as no gesture for gesture models.
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    /// Gesture for each class score column of a gesture model.
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+fn default_threshold() -> f32 {
+    0.9
+}
@@ -0,0 +1,352 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use error_stack::{Result, ResultExt};
+use nalgebra::Vector3;
+use serde::Deserialize;
+
+use super::{CameraProperties, Distortion, StereoConfig};
+use crate::{
+    math::{essential_matrix, matrix3, to_rows},
+    GError,
+};
+
+/// A stereo calibration saved by OpenCV's `FileStorage` as yaml or json. It
+/// fills in the intrinsics, distortion and pose of both cameras, with camera 1
+/// as the left one.
+#[derive(Deserialize, Debug, Clone)]
+pub struct OpenCvCalibration {
+    pub path: PathBuf,
+    /// Reads the distortion coefficients as `cv.fisheye` ones.
+    #[serde(default)]
+    pub fisheye: bool,
+    #[serde(default)]
+    pub nodes: Nodes,
+}
+
+/// Names of the matrices in the file, by default the ones
+/// `triangulation_DLT.py` reads.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Nodes {
+    pub camera_matrix1: String,
+    pub camera_matrix2: String,
+    /// Left out of the file for lenses without distortion.
+    pub distortion1: String,
+    pub distortion2: String,
+    pub rotation: String,
+    pub translation: String,
+    /// Left out of the file to use the one of the rotation and translation.
+    pub fundamental: String,
+}
+
+impl Default for Nodes {
+    fn default() -> Self {
+        Self {
+            camera_matrix1: "CameraMatrixL".to_owned(),
+            camera_matrix2: "CameraMatrixR".to_owned(),
+            distortion1: "DistortionL".to_owned(),
+            distortion2: "DistortionR".to_owned(),
+            rotation: "RotationMatrix".to_owned(),
+            translation: "TranslationMatrix".to_owned(),
+            fundamental: "FundamentalMatrix".to_owned(),
+        }
+    }
+}
+
+/// A `cv::Mat` as `FileStorage` writes it.
+#[derive(Deserialize, Debug)]
+struct Mat {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+impl OpenCvCalibration {
+    /// Reads the file into the cameras. Pairs people by epipolar distance
+    /// unless the config says how already.
+    pub fn apply(
+        &self,
+        camera1: &mut CameraProperties,
+        camera2: &mut CameraProperties,
+        stereo: &mut Option<StereoConfig>,
+    ) -> Result<(), GError> {
+        self.read()
+            .and_then(|mats| self.load(&mats, camera1, camera2, stereo))
+            .attach_printable_lazy(|| format!("In the calibration file {}", self.path.display()))
+    }
+
+    fn load(
+        &self,
+        mats: &HashMap<String, Mat>,
+        camera1: &mut CameraProperties,
+        camera2: &mut CameraProperties,
+        stereo: &mut Option<StereoConfig>,
+    ) -> Result<(), GError> {
+        let nodes = &self.nodes;
+        let k1 = matrix(mats, &nodes.camera_matrix1)?;
+        let k2 = matrix(mats, &nodes.camera_matrix2)?;
+        let distortion1 = self.distortion(mats, &nodes.distortion1)?;
+        let distortion2 = self.distortion(mats, &nodes.distortion2)?;
+        let rotation = matrix(mats, &nodes.rotation)?;
+        let translation = vector(mats, &nodes.translation)?;
+
+        camera1.intrensic_prams = k1;
+        camera1.rotation_matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        camera1.translation_vector = [0.0; 3];
+        camera1.distortion = distortion1.or(camera1.distortion);
+        camera2.intrensic_prams = k2;
+        camera2.rotation_matrix = rotation;
+        camera2.translation_vector = translation;
+        camera2.distortion = distortion2.or(camera2.distortion);
+
+        let stereo = stereo.get_or_insert_with(StereoConfig::default);
+        if stereo.fundamental_matrix.is_none() && stereo.essential_matrix.is_none() {
+            if mats.contains_key(&nodes.fundamental) {
+                stereo.fundamental_matrix = Some(matrix(mats, &nodes.fundamental)?);
+            } else {
+                let e = essential_matrix(&matrix3(&rotation), &Vector3::from(translation));
+                stereo.essential_matrix = Some(to_rows(&e));
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> Result<HashMap<String, Mat>, GError> {
+        let text = fs::read_to_string(&self.path)
+            .change_context(GError::ConfigError)
+            .attach_printable("Couldn't read the calibration file")?;
+
+        match self.path.extension().and_then(|ext| ext.to_str()) {
+            Some("yml" | "yaml") => parse_yaml(&text),
+            Some("json") => parse_json(&text),
+            _ => Err(GError::ConfigError)
+                .attach_printable("Calibration files are read from .yml, .yaml or .json"),
+        }
+    }
+
+    fn distortion(
+        &self,
+        mats: &HashMap<String, Mat>,
+        name: &str,
+    ) -> Result<Option<Distortion>, GError> {
+        let mat = match mats.get(name) {
+            Some(mat) => mat,
+            None => return Ok(None),
+        };
+        check(mat, name)?;
+        let c = &mat.data;
+        if mat.rows != 1 && mat.cols != 1 {
+            return Err(GError::ConfigError).attach_printable(format!(
+                "{} should have a single row or column, is {}x{}",
+                name, mat.rows, mat.cols
+            ));
+        }
+
+        if self.fisheye {
+            if c.len() != 4 {
+                return Err(GError::ConfigError).attach_printable(format!(
+                    "{} should have 4 fisheye coefficients, has {}",
+                    name,
+                    c.len()
+                ));
+            }
+            return Ok(Some(Distortion::Fisheye {
+                k1: c[0],
+                k2: c[1],
+                k3: c[2],
+                k4: c[3],
+            }));
+        }
+
+        if ![4, 5, 8, 12, 14].contains(&c.len()) {
+            return Err(GError::ConfigError).attach_printable(format!(
+                "{} should have 4, 5, 8, 12 or 14 coefficients, has {}",
+                name,
+                c.len()
+            ));
+        }
+        if c.iter().skip(5).any(|c| *c != 0.0) {
+            return Err(GError::ConfigError).attach_printable(format!(
+                "{} uses coefficients past k1, k2, p1, p2 and k3, which aren't supported",
+                name
+            ));
+        }
+        Ok(Some(Distortion::BrownConrady {
+            k1: c[0],
+            k2: c[1],
+            p1: c[2],
+            p2: c[3],
+            k3: c.get(4).copied().unwrap_or(0.0),
+        }))
+    }
+}
+
+fn parse_yaml(text: &str) -> Result<HashMap<String, Mat>, GError> {
+    // FileStorage starts with a %YAML:1.0 directive, which isn't valid yaml
+    let text = match text.strip_prefix("%YAML") {
+        Some(rest) => rest.split_once('\n').map_or("", |(_, rest)| rest),
+        None => text,
+    };
+    let nodes: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(text)
+        .change_context(GError::ConfigError)
+        .attach_printable("The calibration file isn't valid yaml")?;
+
+    // matrices are tagged !!opencv-matrix, anything else isn't one
+    Ok(nodes
+        .into_iter()
+        .filter_map(|(name, node)| {
+            let node = match node {
+                serde_yaml::Value::Tagged(tagged) => tagged.value,
+                node => node,
+            };
+            Some((name, serde_yaml::from_value(node).ok()?))
+        })
+        .collect())
+}
+
+fn parse_json(text: &str) -> Result<HashMap<String, Mat>, GError> {
+    let nodes: HashMap<String, serde_json::Value> = serde_json::from_str(text)
+        .change_context(GError::ConfigError)
+        .attach_printable("The calibration file isn't valid json")?;
+
+    Ok(nodes
+        .into_iter()
+        .filter_map(|(name, node)| Some((name, serde_json::from_value(node).ok()?)))
+        .collect())
+}
+
+fn get<'a>(mats: &'a HashMap<String, Mat>, name: &str) -> Result<&'a Mat, GError> {
+    let mat = mats
+        .get(name)
+        .ok_or(GError::ConfigError)
+        .attach_printable_lazy(|| format!("There's no matrix named {}", name))?;
+    check(mat, name)?;
+    Ok(mat)
+}
+
+fn check(mat: &Mat, name: &str) -> Result<(), GError> {
+    if mat.data.len() != mat.rows * mat.cols {
+        return Err(GError::ConfigError).attach_printable(format!(
+            "{} has {} values for {}x{}",
+            name,
+            mat.data.len(),
+            mat.rows,
+            mat.cols
+        ));
+    }
+    Ok(())
+}
+
+fn matrix(mats: &HashMap<String, Mat>, name: &str) -> Result<[[f64; 3]; 3], GError> {
+    let mat = get(mats, name)?;
+    if (mat.rows, mat.cols) != (3, 3) {
+        return Err(GError::ConfigError).attach_printable(format!(
+            "{} should be 3x3, is {}x{}",
+            name, mat.rows, mat.cols
+        ));
+    }
+    let d = &mat.data;
+    Ok([[d[0], d[1], d[2]], [d[3], d[4], d[5]], [d[6], d[7], d[8]]])
+}
+
+fn vector(mats: &HashMap<String, Mat>, name: &str) -> Result<[f64; 3], GError> {
+    let mat = get(mats, name)?;
+    if (mat.rows, mat.cols) != (3, 1) && (mat.rows, mat.cols) != (1, 3) {
+        return Err(GError::ConfigError).attach_printable(format!(
+            "{} should be 3x1, is {}x{}",
+            name, mat.rows, mat.cols
+        ));
+    }
+    Ok([mat.data[0], mat.data[1], mat.data[2]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_opencv_files() {
+        let yaml = r#"%YAML:1.0
+---
+CameraMatrixL: !!opencv-matrix
+   rows: 3
+   cols: 3
+   dt: d
+   data: [ 1.4253555975305719e+03, 0., 7.2552788750799868e+02, 0.,
+       1.4039605486267199e+03, 4.0030984906993211e+02, 0., 0., 1. ]
+CameraMatrixR: !!opencv-matrix
+   rows: 3
+   cols: 3
+   dt: d
+   data: [ 1400., 0., 700., 0., 1390., 410., 0., 0., 1. ]
+DistortionL: !!opencv-matrix
+   rows: 1
+   cols: 5
+   dt: d
+   data: [ -0.41, 0.22, 0.001, -0.0005, 0. ]
+RotationMatrix: !!opencv-matrix
+   rows: 3
+   cols: 3
+   dt: d
+   data: [ 1., 0., 0., 0., 1., 0., 0., 0., 1. ]
+TranslationMatrix: !!opencv-matrix
+   rows: 3
+   cols: 1
+   dt: d
+   data: [ -23.8, 0., -0.3 ]
+retStereo: 0.42
+"#;
+        let json = r#"{
+    "CameraMatrixL": {
+        "type_id": "opencv-matrix",
+        "rows": 3,
+        "cols": 3,
+        "dt": "d",
+        "data": [ 1400.0, 0.0, 700.0, 0.0, 1390.0, 410.0, 0.0, 0.0, 1.0 ]
+    },
+    "CameraMatrixR": {
+        "type_id": "opencv-matrix",
+        "rows": 2,
+        "cols": 3,
+        "dt": "d",
+        "data": [ 1400.0, 0.0, 700.0, 0.0, 1390.0, 410.0 ]
+    }
+}"#;
+        let dir = std::env::temp_dir();
+        let write = |name: &str, text: &str| {
+            let path = dir.join(format!("calibration_{}_{}", std::process::id(), name));
+            fs::write(&path, text).unwrap();
+            OpenCvCalibration {
+                path,
+                fisheye: false,
+                nodes: Nodes::default(),
+            }
+        };
+
+        let (mut camera1, mut camera2) =
+            (CameraProperties::test_new(), CameraProperties::test_new());
+        let mut stereo = None;
+        let calibration = write("stereo.yml", yaml);
+        calibration
+            .apply(&mut camera1, &mut camera2, &mut stereo)
+            .unwrap();
+        assert_eq!(camera1.intrensic_prams[0][0], 1.4253555975305719e+03);
+        assert_eq!(camera2.intrensic_prams[1][2], 410.0);
+        assert_eq!(camera2.translation_vector, [-23.8, 0.0, -0.3]);
+        assert!(matches!(
+            camera1.distortion,
+            Some(Distortion::BrownConrady { k1, .. }) if k1 == -0.41
+        ));
+        assert!(camera2.distortion.is_none());
+        assert!(stereo.unwrap().essential_matrix.is_some());
+        fs::remove_file(calibration.path).unwrap();
+
+        // camera 2 isn't 3x3
+        let calibration = write("stereo.json", json);
+        let err = calibration
+            .apply(&mut camera1, &mut camera2, &mut None)
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains("CameraMatrixR should be 3x3, is 2x3"));
+        fs::remove_file(calibration.path).unwrap();
+    }
+}
@@ -0,0 +1,10 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// Records every processed frame pair with its predictions to a session file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RecordConfig {
+    /// Has to not exist yet, sessions are never overwritten.
+    pub path: PathBuf,
+}
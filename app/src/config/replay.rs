@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+/// Replays recorded stereo pairs instead of capturing from the camera process.
+///
+/// `path` is either a directory or a tar archive, like a recorded session,
+/// holding a `cam1/` and a `cam2/` folder, pairs are matched by file name and
+/// played in name order. Frames are png, jpeg or raw rgb (`.rgb`/`.raw`) of the
+/// image size set for each camera.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ReplayConfig {
+    pub path: PathBuf,
+    /// Pairs played per second.
+    #[serde(default = "default_fps")]
+    pub fps: f32,
+    /// Start over after the last pair instead of stopping.
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+fn default_fps() -> f32 {
+    2.0
+}
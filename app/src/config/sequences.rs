@@ -0,0 +1,58 @@
+use serde::Deserialize;
+
+use crate::models::Gesture;
+
+/// Gestures done one after the other by the same person, recognized as one
+/// event that devices can map to an action by its name, like
+///
+/// ```toml
+/// [[sequences]]
+/// name = "double_toggle"
+/// steps = ["toggle", "toggle"]
+///
+/// [[sequences]]
+/// name = "long_point"
+/// steps = [{ gesture = "point", hold = 1500 }]
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+pub struct SequenceConfig {
+    /// Must not be the name of a gesture, checked when the config is opened.
+    pub name: String,
+    pub steps: Vec<Step>,
+    /// Milliseconds allowed between the end of one step and the start of the next.
+    #[serde(default = "default_gap")]
+    pub gap: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "StepConfig")]
+pub struct Step {
+    pub gesture: Gesture,
+    /// Milliseconds the gesture has to be held for the step to count.
+    pub hold: u64,
+}
+
+/// A step is either just a gesture or a table with the time to hold it.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StepConfig {
+    Gesture(Gesture),
+    Held {
+        gesture: Gesture,
+        #[serde(default)]
+        hold: u64,
+    },
+}
+
+impl From<StepConfig> for Step {
+    fn from(step: StepConfig) -> Self {
+        match step {
+            StepConfig::Gesture(gesture) => Self { gesture, hold: 0 },
+            StepConfig::Held { gesture, hold } => Self { gesture, hold },
+        }
+    }
+}
+
+fn default_gap() -> u64 {
+    1000
+}
@@ -0,0 +1,53 @@
+use nalgebra::Matrix3;
+use serde::Deserialize;
+
+use super::CameraProperties;
+use crate::math::matrix3;
+
+/// Calibration between the two cameras as `cv::stereoCalibrate` gives it,
+/// with camera 1 as the left one.
+#[derive(Deserialize, Debug, Clone)]
+pub struct StereoConfig {
+    /// Takes a pixel in camera 1 to its epipolar line in camera 2.
+    #[serde(default)]
+    pub fundamental_matrix: Option<[[f64; 3]; 3]>,
+    /// Used together with the intrinsics of both cameras when there's no
+    /// fundamental matrix.
+    #[serde(default)]
+    pub essential_matrix: Option<[[f64; 3]; 3]>,
+    /// Pixels a person may be off the epipolar line of the other camera and
+    /// still be paired.
+    #[serde(default = "default_max_residual")]
+    pub max_residual: f32,
+}
+
+impl StereoConfig {
+    pub fn fundamental(
+        &self,
+        camera1: &CameraProperties,
+        camera2: &CameraProperties,
+    ) -> Option<Matrix3<f64>> {
+        if let Some(f) = self.fundamental_matrix {
+            return Some(matrix3(&f));
+        }
+
+        let e = matrix3(&self.essential_matrix?);
+        let k1_inv = matrix3(&camera1.intrensic_prams).try_inverse()?;
+        let k2_inv = matrix3(&camera2.intrensic_prams).try_inverse()?;
+        Some(k2_inv.transpose() * e * k1_inv)
+    }
+}
+
+impl Default for StereoConfig {
+    fn default() -> Self {
+        Self {
+            fundamental_matrix: None,
+            essential_matrix: None,
+            max_residual: default_max_residual(),
+        }
+    }
+}
+
+fn default_max_residual() -> f32 {
+    10.0
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::Process;
+
+/// How long to wait for each process to answer a request, in milliseconds.
+/// A process silent for `hung` is taken down so a restarted one can replace it.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Timeouts {
+    pub hpe: u64,
+    pub gesture: u64,
+    pub head: u64,
+    pub cam: u64,
+    pub hung: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            hpe: 2000,
+            gesture: 1000,
+            head: 1000,
+            cam: 2000,
+            hung: 10000,
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn get(&self, process: Process) -> Duration {
+        let ms = match process {
+            Process::HPE => self.hpe,
+            Process::GestureRecognition => self.gesture,
+            Process::HeadDetection => self.head,
+            Process::Camera => self.cam,
+        };
+
+        Duration::from_millis(ms)
+    }
+
+    pub fn hung(&self) -> Duration {
+        Duration::from_millis(self.hung)
+    }
+}
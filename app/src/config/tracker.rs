@@ -0,0 +1,26 @@
+use serde::Deserialize;
+
+/// How people are followed from frame to frame in each camera.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TrackerConfig {
+    /// Pixels a detection may be from where a person was expected and still be them.
+    pub max_distance: f32,
+    /// Frames a person can go undetected before their id is dropped.
+    pub max_missed: u32,
+    /// How quickly people are expected to change speed, in pixels per second squared.
+    pub process_noise: f32,
+    /// How far off a detection is expected to be, in pixels.
+    pub measurement_noise: f32,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            max_distance: 100.0,
+            max_missed: 5,
+            process_noise: 200.0,
+            measurement_noise: 10.0,
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use serde::Deserialize;
+
+/// How the position of a person is worked out from what both cameras see.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Triangulation {
+    /// Closest point between the rays through the detections, from the field
+    /// of view and orientation of each camera.
+    #[default]
+    Rays,
+    /// Direct linear transform with the calibrated projection `K[R|t]` of
+    /// each camera.
+    Dlt,
+}
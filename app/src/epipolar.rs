@@ -0,0 +1,130 @@
+//! Pairs people seen by both cameras by how far each one is from the epipolar
+//! line of the other.
+
+use nalgebra::{Matrix3, Vector3};
+
+use crate::{
+    config::{CameraProperties, StereoConfig},
+    tracker, HasImagePosition,
+};
+
+/// A detection in camera 1 and the one in camera 2 showing the same person.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub cam1: usize,
+    pub cam2: usize,
+    /// Mean distance in pixels of both detections from the epipolar line of the other.
+    pub residual: f32,
+}
+
+pub struct EpipolarMatcher {
+    fundamental: Matrix3<f64>,
+    max_residual: f32,
+}
+
+impl EpipolarMatcher {
+    /// Returns `None` when the calibration doesn't give a fundamental matrix.
+    pub fn new(
+        stereo: &StereoConfig,
+        camera1: &CameraProperties,
+        camera2: &CameraProperties,
+    ) -> Option<Self> {
+        Some(Self {
+            fundamental: stereo.fundamental(camera1, camera2)?,
+            max_residual: stereo.max_residual,
+        })
+    }
+
+    /// Symmetric epipolar distance between a pixel in camera 1 and one in camera 2.
+    pub fn residual(&self, p1: &impl HasImagePosition, p2: &impl HasImagePosition) -> f32 {
+        let x1 = Vector3::new(p1.image_x() as f64, p1.image_y() as f64, 1.0);
+        let x2 = Vector3::new(p2.image_x() as f64, p2.image_y() as f64, 1.0);
+
+        let distance =
+            |line: Vector3<f64>, x: &Vector3<f64>| line.dot(x).abs() / line[0].hypot(line[1]);
+        let d2 = distance(self.fundamental * x1, &x2);
+        let d1 = distance(self.fundamental.transpose() * x2, &x1);
+
+        ((d1 + d2) / 2.0) as f32
+    }
+
+    /// Pairs the detections at the lowest total residual, leaving out pairs
+    /// further apart than `max_residual`.
+    pub fn matches<A: HasImagePosition, B: HasImagePosition>(
+        &self,
+        cam1: &[A],
+        cam2: &[B],
+    ) -> Vec<Match> {
+        let cost: Vec<Vec<f32>> = cam1
+            .iter()
+            .map(|a| cam2.iter().map(|b| self.residual(a, b)).collect())
+            .collect();
+
+        tracker::assign(&cost, self.max_residual)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, j)| {
+                let j = j?;
+                Some(Match {
+                    cam1: i,
+                    cam2: j,
+                    residual: cost[i][j],
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Rotation3;
+
+    use super::*;
+    use crate::{
+        math::{essential_matrix, to_rows},
+        models::HeadPrediction,
+    };
+
+    #[test]
+    fn match_people_at_the_same_depth() {
+        let camera1 = CameraProperties::test_new();
+        let camera2 = CameraProperties::test_new();
+        let k = Matrix3::from_fn(|row, col| camera1.intrensic_prams[row][col]);
+
+        // camera 2 to the right of camera 1 and turned towards it
+        let r = Rotation3::from_euler_angles(0.0, -0.2, 0.0).into_inner();
+        let t = Vector3::new(-30.0, 1.0, 3.0);
+        let stereo = StereoConfig {
+            fundamental_matrix: None,
+            essential_matrix: Some(to_rows(&essential_matrix(&r, &t))),
+            max_residual: 5.0,
+        };
+        let matcher = EpipolarMatcher::new(&stereo, &camera1, &camera2).unwrap();
+
+        let project = |x: Vector3<f64>| {
+            let x = k * x;
+            HeadPrediction {
+                nose_x: (x.x / x.z) as f32,
+                nose_y: (x.y / x.z) as f32,
+                ..Default::default()
+            }
+        };
+        // three people 200 units out, the last one isn't seen by camera 2
+        let people = [
+            Vector3::new(-40.0, -10.0, 200.0),
+            Vector3::new(10.0, 5.0, 200.0),
+            Vector3::new(50.0, -5.0, 200.0),
+        ];
+        let cam1: Vec<_> = people.iter().map(|x| project(*x)).collect();
+        let cam2: Vec<_> = people[..2]
+            .iter()
+            .rev()
+            .map(|x| project(r * x + t))
+            .collect();
+
+        let matches = matcher.matches(&cam1, &cam2);
+        let pairs: Vec<_> = matches.iter().map(|m| (m.cam1, m.cam2)).collect();
+        assert_eq!(pairs, [(0, 1), (1, 0)]);
+        assert!(matches.iter().all(|m| m.residual < 0.1));
+    }
+}
@@ -6,10 +6,17 @@ use error_stack::Context;
 pub enum GError {
     CommError,
     IpcError,
+    ProtocolError,
     MathError,
     ConfigError,
     ModelUninit,
+    WorkerDown,
+    Timeout,
+    InferenceError,
     CameraError,
+    ReplayEnded,
+    SessionError,
+    ActuatorError,
 }
 
 impl fmt::Display for GError {
@@ -17,10 +24,17 @@ impl fmt::Display for GError {
         match self {
             Self::CommError => write!(f, "Error in channel"),
             Self::IpcError => write!(f, "Error while communicating with process"),
+            Self::ProtocolError => write!(f, "Process does not speak the expected IPC protocol"),
             Self::ConfigError => write!(f, "Error in loading config"),
             Self::MathError => write!(f, "Error in math operation"),
             Self::ModelUninit => write!(f, "Model used before initializing"),
+            Self::WorkerDown => write!(f, "Process is disconnected"),
+            Self::Timeout => write!(f, "Timed out waiting for process"),
+            Self::InferenceError => write!(f, "Error while running model"),
             Self::CameraError => write!(f, "Camera Error"),
+            Self::ReplayEnded => write!(f, "No more recorded frames to replay"),
+            Self::SessionError => write!(f, "Error while reading or writing session file"),
+            Self::ActuatorError => write!(f, "Error while driving device"),
         }
     }
 }
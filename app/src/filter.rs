@@ -0,0 +1,188 @@
+//! Holds gestures back until they're steady, so a single noisy frame doesn't
+//! switch anything.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::FilterConfig,
+    models::{Gesture, GesturePrediction},
+    tracker::TrackId,
+    HasTrackId,
+};
+
+pub struct GestureFilter {
+    config: FilterConfig,
+    people: HashMap<TrackId, Person>,
+    /// When each device was last acted on and for whom.
+    last_action: HashMap<String, (Instant, Option<TrackId>)>,
+}
+
+#[derive(Debug)]
+struct Person {
+    /// Gestures of the last `window` frames.
+    history: VecDeque<Gesture>,
+    /// Gesture winning the vote and since when.
+    leading: Gesture,
+    since: Instant,
+    fired: bool,
+}
+
+impl GestureFilter {
+    pub fn new(config: &FilterConfig) -> Self {
+        Self {
+            config: config.clone(),
+            people: HashMap::new(),
+            last_action: HashMap::new(),
+        }
+    }
+
+    /// Takes the tracked gestures of a frame and returns the gesture to act on
+    /// for each of them, once per gesture or every frame for gestures carrying a value.
+    pub fn update(&mut self, gestures: &[GesturePrediction], now: Instant) -> Vec<Option<Gesture>> {
+        gestures
+            .iter()
+            .map(|prediction| {
+                self.people
+                    .entry(prediction.track_id()?)
+                    .or_insert_with(|| Person::new(now))
+                    .update(prediction, &self.config, now)
+            })
+            .collect()
+    }
+
+    /// Forgets the people the tracker lost.
+    pub fn retain(&mut self, alive: impl Fn(TrackId) -> bool) {
+        self.people.retain(|id, _| alive(*id));
+    }
+
+    /// Whether the device is done cooling down from its last action, or that
+    /// action was on behalf of `exempt`.
+    pub fn cooled_down(&self, device: &str, exempt: Option<TrackId>, now: Instant) -> bool {
+        let cooldown = Duration::from_millis(self.config.cooldown);
+        self.last_action.get(device).is_none_or(|(last, person)| {
+            now - *last >= cooldown || (exempt.is_some() && *person == exempt)
+        })
+    }
+
+    /// Starts the cooldown of a device once an action on it went through.
+    pub fn mark_acted(&mut self, device: &str, person: Option<TrackId>, now: Instant) {
+        self.last_action.insert(device.to_owned(), (now, person));
+    }
+}
+
+impl Person {
+    fn new(now: Instant) -> Self {
+        Self {
+            history: VecDeque::new(),
+            leading: Gesture::None,
+            since: now,
+            fired: false,
+        }
+    }
+
+    fn update(
+        &mut self,
+        prediction: &GesturePrediction,
+        config: &FilterConfig,
+        now: Instant,
+    ) -> Option<Gesture> {
+        self.history.push_back(prediction.gesture.clone());
+        while self.history.len() > config.window.max(1) {
+            self.history.pop_front();
+        }
+
+        let winner = self
+            .history
+            .iter()
+            .filter(|gesture| !gesture.is_none())
+            .map(|gesture| {
+                let votes = self.history.iter().filter(|g| *g == gesture).count();
+                (gesture, votes)
+            })
+            .max_by_key(|(_, votes)| *votes)
+            .filter(|(_, votes)| *votes >= config.votes)
+            .map(|(gesture, _)| gesture.clone())
+            .unwrap_or_default();
+
+        if winner != self.leading {
+            self.leading = winner;
+            self.since = now;
+            self.fired = false;
+        }
+        if self.leading.is_none() {
+            return None;
+        }
+
+        // gestures setting a level keep acting while they're shown
+        let act = if self.fired {
+            prediction.value.is_some() && prediction.gesture == self.leading
+        } else {
+            now - self.since >= Duration::from_millis(config.hold)
+        };
+        if !act {
+            return None;
+        }
+
+        self.fired = true;
+        Some(self.leading.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debounce_gestures() {
+        use Gesture::*;
+
+        let mut filter = GestureFilter::new(&FilterConfig {
+            window: 3,
+            votes: 2,
+            hold: 500,
+            cooldown: 3000,
+        });
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut feed = |ms, people: &[(TrackId, Gesture)]| {
+            let gestures: Vec<_> = people
+                .iter()
+                .map(|(id, gesture)| GesturePrediction {
+                    id: Some(*id),
+                    gesture: gesture.clone(),
+                    ..Default::default()
+                })
+                .collect();
+            filter.update(&gestures, at(ms))
+        };
+
+        let nothing = vec![Option::None; 2];
+
+        // a single frame isn't enough, the second person swaps places in the frame
+        assert_eq!(feed(0, &[(1, Toggle), (2, None)]), nothing);
+        assert_eq!(feed(200, &[(2, Point), (1, None)]), nothing);
+        assert_eq!(feed(400, &[(2, Point), (1, Toggle)]), nothing);
+        assert_eq!(feed(700, &[(2, Point), (1, Toggle)]), nothing);
+        // held long enough, two out of three frames count, only acted on once
+        assert_eq!(
+            feed(900, &[(2, Point), (1, None)]),
+            [Some(Point), Some(Toggle)]
+        );
+        assert_eq!(feed(1100, &[(2, Point), (1, Toggle)]), nothing);
+
+        // only actions that went through start a cooldown
+        assert!(filter.cooled_down("Lamp", Option::None, at(0)));
+        filter.mark_acted("Lamp", Some(1), at(0));
+        filter.mark_acted("Fan", Option::None, at(1000));
+        assert!(!filter.cooled_down("Lamp", Option::None, at(2000)));
+        assert!(!filter.cooled_down("Fan", Option::None, at(3000)));
+        assert!(filter.cooled_down("Lamp", Option::None, at(3000)));
+        // unless exempt for the person who started it
+        assert!(filter.cooled_down("Lamp", Some(1), at(2000)));
+        assert!(!filter.cooled_down("Lamp", Some(2), at(2000)));
+        assert!(!filter.cooled_down("Fan", Some(1), at(2000)));
+    }
+}
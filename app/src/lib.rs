@@ -1,26 +1,45 @@
 use camera::CameraProc;
-use config::Config;
-use error_stack::{Result, ResultExt};
+use config::{BackendConfig, Config};
+use error_stack::{Report, Result, ResultExt};
+use protocol::{Header, Hello, MessageKind, PixelFormat};
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     fmt,
-    io::Read,
     os::unix::net::{UnixListener, UnixStream},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
     usize,
 };
 
 use models::{GestureDetection, HeadDetection, HeadPoseEstimation};
+use traits::Reconnect;
 
 mod error;
+#[cfg(test)]
+mod mock;
 
+pub mod actuators;
+pub mod calibration;
 pub mod camera;
 pub mod config;
+pub mod epipolar;
+pub mod filter;
 pub mod math;
 pub mod models;
+pub mod pipeline;
+pub mod protocol;
+pub mod sequence;
+pub mod session;
+pub mod state;
+pub mod tracker;
 pub mod traits;
 
 pub use error::GError;
-pub use traits::{HasGlamPosition, HasGlamQuat, HasImagePosition, ImageProcessor};
+pub use protocol::FrameId;
+pub use traits::{
+    FrameSource, HasGlamPosition, HasGlamQuat, HasImagePosition, HasTrackId, ImageProcessor,
+};
 
 pub struct ImageCoords {
     pub x: f32,
@@ -60,7 +79,17 @@ impl ImageCoords {
     }
 }
 
-#[derive(PartialEq, Eq, Hash)]
+impl HasImagePosition for ImageCoords {
+    fn image_x(&self) -> f32 {
+        self.x
+    }
+
+    fn image_y(&self) -> f32 {
+        self.y
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum Process {
     HPE,
     GestureRecognition,
@@ -68,14 +97,17 @@ pub enum Process {
     Camera,
 }
 
-impl From<&str> for Process {
-    fn from(value: &str) -> Self {
+impl TryFrom<&str> for Process {
+    type Error = Report<GError>;
+
+    fn try_from(value: &str) -> Result<Self, GError> {
         match value {
-            "hpe" | "directmhp" => Self::HPE,
-            "ge" | "gesture" => Self::GestureRecognition,
-            "head" => Self::HeadDetection,
-            "cam" => Self::Camera,
-            _ => panic!("invalid"),
+            "hpe" | "directmhp" => Ok(Self::HPE),
+            "ge" | "gesture" => Ok(Self::GestureRecognition),
+            "head" => Ok(Self::HeadDetection),
+            "cam" => Ok(Self::Camera),
+            _ => Err(GError::ProtocolError)
+                .attach_printable(format!("Unknown process kind {:?}", value)),
         }
     }
 }
@@ -92,7 +124,7 @@ impl fmt::Display for Process {
 }
 
 pub struct Models {
-    pset: HashSet<Process>,
+    workers: Arc<Mutex<HashMap<Process, Hello>>>,
     num: usize,
     listener: UnixListener,
     hpe: Option<HeadPoseEstimation>,
@@ -104,7 +136,7 @@ pub struct Models {
 impl Models {
     pub fn new(num: usize, listener: UnixListener) -> Self {
         Self {
-            pset: HashSet::new(),
+            workers: Arc::new(Mutex::new(HashMap::new())),
             hpe: None,
             gesture: None,
             head: None,
@@ -146,7 +178,53 @@ impl Models {
         }
     }
 
-    pub fn add_process(&mut self, model: Process, stream: UnixStream, config: &Config) {
+    /// Hello message the connected process of the given kind announced itself with.
+    pub fn worker_info(&self, process: Process) -> Option<Hello> {
+        self.workers.lock().unwrap().get(&process).cloned()
+    }
+
+    fn has_model(&self, process: Process) -> bool {
+        match process {
+            Process::HPE => self.hpe.is_some(),
+            Process::GestureRecognition => self.gesture.is_some(),
+            Process::HeadDetection => self.head.is_some(),
+            Process::Camera => self.cams.is_some(),
+        }
+    }
+
+    /// Loads the models configured to run in this process instead of a separate one.
+    pub fn start_local(&mut self, config: &Config) -> Result<(), GError> {
+        if let BackendConfig::Onnx(onnx) = &config.models.gesture {
+            self.gesture = Some(models::local_worker(onnx)?);
+        }
+        if let BackendConfig::Onnx(onnx) = &config.models.head {
+            self.head = Some(models::local_worker(onnx)?);
+        }
+        if let BackendConfig::Onnx(_) = &config.models.hpe {
+            return Err(GError::ConfigError)
+                .attach_printable("Head pose estimation can only run as a separate process");
+        }
+
+        Ok(())
+    }
+
+    /// Whether the last round trip to the process of the given kind succeeded.
+    pub fn is_up(&self, process: Process) -> bool {
+        match process {
+            Process::HPE => self.hpe.as_ref().is_some_and(|m| m.is_up()),
+            Process::GestureRecognition => self.gesture.as_ref().is_some_and(|m| m.is_up()),
+            Process::HeadDetection => self.head.as_ref().is_some_and(|m| m.is_up()),
+            Process::Camera => self.cams.as_ref().is_some_and(|m| m.is_up()),
+        }
+    }
+
+    pub fn add_process(
+        &mut self,
+        model: Process,
+        hello: Hello,
+        stream: UnixStream,
+        config: &Config,
+    ) {
         match model {
             Process::HPE => {
                 let model = HeadPoseEstimation::new(stream);
@@ -183,25 +261,277 @@ impl Models {
                 self.cams = Some(camp)
             }
         }
-        self.pset.insert(model);
+        self.workers.lock().unwrap().insert(model, hello);
     }
 
     pub fn len(&self) -> usize {
-        self.pset.len()
+        self.workers.lock().unwrap().len()
     }
 
     pub fn wait_for_connection(&mut self, config: &Config) {
         while self.len() < self.num {
-            let (mut stream, _addr) = self.listener.accept().unwrap();
+            let (stream, _addr) = self.listener.accept().unwrap();
+
+            let worker = handshake(&stream, config.timeouts.hung()).and_then(|(model, hello)| {
+                if self.has_model(model) {
+                    Err(GError::ProtocolError)
+                        .attach_printable(format!("A {} process is already connected", model))
+                } else if model == Process::Camera && config.replay.is_some() {
+                    // it would take the place of a model in the count
+                    Err(GError::ProtocolError)
+                        .attach_printable("Replaying recorded frames, no camera is expected")
+                } else {
+                    Ok((model, hello))
+                }
+            });
+            let (model, hello) = match worker.and_then(|w| acknowledge(&stream).map(|_| w)) {
+                Ok(worker) => worker,
+                Err(e) => {
+                    reject(&stream, e);
+                    continue;
+                }
+            };
+
+            println!(
+                "{} connected (version {}, model {})",
+                model,
+                hello.version,
+                hello.model.as_deref().unwrap_or("unknown")
+            );
+            self.add_process(model, hello, stream, config);
+            println!("Processes connected: {}", self.len())
+        }
 
-            let mut buffer = [0; 1024];
-            let bytes_read = stream.read(&mut buffer).unwrap();
-            let model: Process = String::from_utf8_lossy(&buffer[..bytes_read])
-                .as_ref()
-                .into();
+        if let Err(e) = self.supervise(config.timeouts.hung()) {
+            println!("Couldn't watch for restarted processes: {:?}", e);
+        }
+    }
 
-            self.add_process(model, stream, config);
-            println!("Processes connected: {}", self.len())
+    /// Keeps accepting connections in the background, so a crashed process can be
+    /// restarted and take over from its dead connection without restarting us.
+    fn supervise(&self, hung: Duration) -> Result<JoinHandle<()>, GError> {
+        let listener = self.listener.try_clone().change_context(GError::IpcError)?;
+        let workers = self.workers.clone();
+        let hpe = self.hpe.clone();
+        let gesture = self.gesture.clone();
+        let head = self.head.clone();
+        let cams = self.cams.clone();
+
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+
+                let (model, hello) = match handshake(&stream, hung) {
+                    Ok(worker) => worker,
+                    Err(e) => {
+                        reject(&stream, e);
+                        continue;
+                    }
+                };
+
+                let res = match model {
+                    Process::HPE => hand_over(hpe.as_ref().and_then(|m| m.ipc()), model, &stream),
+                    Process::GestureRecognition => {
+                        hand_over(gesture.as_ref().and_then(|m| m.ipc()), model, &stream)
+                    }
+                    Process::HeadDetection => {
+                        hand_over(head.as_ref().and_then(|m| m.ipc()), model, &stream)
+                    }
+                    Process::Camera => hand_over(cams.as_ref(), model, &stream),
+                };
+
+                match res {
+                    Ok(()) => {
+                        println!("{} reconnected (version {})", model, hello.version);
+                        workers.lock().unwrap().insert(model, hello);
+                    }
+                    Err(e) => reject(&stream, e),
+                }
+            }
+        }))
+    }
+}
+
+/// How long a newly connected process has to send its hello.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads the hello frame of a newly connected process and checks that the
+/// orchestrator can work with it. Afterwards reads give up once the process
+/// has been silent for `hung`, so the worker notices a process that is stuck.
+fn handshake(stream: &UnixStream, hung: Duration) -> Result<(Process, Hello), GError> {
+    // connections are accepted one at a time, a silent process mustn't hold up the rest
+    stream
+        .set_read_timeout(Some(HELLO_TIMEOUT))
+        .change_context(GError::IpcError)?;
+    let (header, msg) = protocol::read_frame(stream)
+        .attach_printable_lazy(|| format!("No hello within {:?}", HELLO_TIMEOUT))?;
+    stream
+        .set_read_timeout(Some(hung))
+        .change_context(GError::IpcError)?;
+    header.expect_kind(MessageKind::Hello)?;
+
+    let hello = Hello::from_slice(&msg)?;
+    let model = Process::try_from(hello.process.as_str())?;
+
+    // every frame on the wire is raw rgb for now
+    if !hello.supports(PixelFormat::Rgb888) {
+        return Err(GError::ProtocolError).attach_printable(format!(
+            "{} process does not support {}",
+            model,
+            PixelFormat::Rgb888
+        ));
+    }
+    if hello.max_batch == 0 {
+        return Err(GError::ProtocolError)
+            .attach_printable(format!("{} process announced a batch size of 0", model));
+    }
+
+    Ok((model, hello))
+}
+
+fn acknowledge(stream: &UnixStream) -> Result<(), GError> {
+    protocol::write_frame(stream, &Header::new(MessageKind::Hello, 0, 0), &[])
+}
+
+fn reject(stream: &UnixStream, e: Report<GError>) {
+    println!("Rejected process: {:?}", e);
+    let reason = format!("{:?}", e);
+    let _ = protocol::write_frame(
+        stream,
+        &Header::new(MessageKind::Error, 0, reason.len()),
+        reason.as_bytes(),
+    );
+}
+
+/// Gives the stream of a restarted process to the worker thread of the same kind.
+/// A second process is turned away while the one connected still answers.
+fn hand_over<T: Reconnect>(
+    model: Option<&T>,
+    kind: Process,
+    stream: &UnixStream,
+) -> Result<(), GError> {
+    let model = match model {
+        Some(model) => model,
+        None => {
+            return Err(GError::ModelUninit)
+                .attach_printable(format!("No {} process was expected", kind))
         }
+    };
+    if model.is_up() {
+        return Err(GError::ProtocolError)
+            .attach_printable(format!("A {} process is already connected", kind));
+    }
+
+    let stream = stream.try_clone().change_context(GError::IpcError)?;
+    acknowledge(&stream)?;
+    model.replace_stream(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn replace_crashed_process() {
+        let config: Config = toml::from_str(mock::CONFIG).unwrap();
+        let socket = mock::socket_path("reconnect");
+        let mut models = Models::new(1, UnixListener::bind(&socket).unwrap());
+        let head = json!({"prediction": [{"nose_x": 32.0, "nose_y": 24.0}]});
+        let img: Arc<[u8]> = vec![0; 64 * 48 * 3].into();
+
+        let first = mock::model(&socket, "head", vec![head.clone()]);
+        models.wait_for_connection(&config);
+        let worker = models.head_detection().unwrap();
+        worker.send(1, img.clone(), 64, 48).unwrap();
+        assert_eq!(worker.recv(1).unwrap().frame, 1);
+
+        // the mock panics on being turned away
+        assert!(mock::model(&socket, "head", vec![]).join().is_err());
+
+        // out of replies, it hangs up
+        first.join().unwrap();
+        worker.send(2, img.clone(), 64, 48).unwrap();
+        assert!(worker.recv(2).is_err());
+        assert!(!models.is_up(Process::HeadDetection));
+
+        mock::model(&socket, "head", vec![head]);
+        // the stream is only handed over after the hello is acknowledged
+        let served = (3..100).any(|frame| {
+            worker.send(frame, img.clone(), 64, 48).unwrap();
+            match worker.recv(frame) {
+                Ok(preds) => preds.frame == frame,
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(10));
+                    false
+                }
+            }
+        });
+        assert!(served);
+        assert!(models.is_up(Process::HeadDetection));
+
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn skip_frames_behind_slow_process() {
+        let config: Config = toml::from_str(mock::CONFIG).unwrap();
+        let socket = mock::socket_path("slow");
+        let mut models = Models::new(1, UnixListener::bind(&socket).unwrap());
+        let head = json!({"prediction": [{"nose_x": 32.0, "nose_y": 24.0}]});
+        let img: Arc<[u8]> = vec![0; 64 * 48 * 3].into();
+
+        // hangs up after two answers, so frame 3 is only served if frame 2 is skipped
+        let delay = Duration::from_millis(200);
+        mock::slow_model(&socket, "head", vec![head.clone(), head], delay);
+        models.wait_for_connection(&config);
+        let worker = models.head_detection().unwrap();
+
+        worker.send(1, img.clone(), 64, 48).unwrap();
+        let err = worker.recv_timeout(1, delay / 4).unwrap_err();
+        assert!(matches!(err.current_context(), GError::Timeout));
+
+        // both queue up while frame 1 is still being worked on
+        worker.send(2, img.clone(), 64, 48).unwrap();
+        worker.send(3, img, 64, 48).unwrap();
+        // the late answer to frame 1 and the skipped frame 2 are discarded
+        let preds = worker.recv_timeout(3, delay * 5).unwrap();
+        assert_eq!(preds.frame, 3);
+
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn take_down_hung_process() {
+        let config = format!("{}\n[timeouts]\nhung = 100", mock::CONFIG);
+        let config: Config = toml::from_str(&config).unwrap();
+        let socket = mock::socket_path("hung");
+        let mut models = Models::new(1, UnixListener::bind(&socket).unwrap());
+
+        let hung = {
+            let socket = socket.clone();
+            thread::spawn(move || {
+                let stream = mock::connect(&socket, "head");
+                protocol::read_frame(&stream).unwrap();
+                // never answers, holds on to the connection until the worker gives up
+                assert!(protocol::read_frame(&stream).is_err());
+            })
+        };
+        models.wait_for_connection(&config);
+        let worker = models.head_detection().unwrap();
+
+        worker.send(1, vec![0; 64 * 48 * 3].into(), 64, 48).unwrap();
+        assert!(worker.recv_timeout(1, Duration::from_secs(1)).is_err());
+        assert!(!models.is_up(Process::HeadDetection));
+        hung.join().unwrap();
+
+        std::fs::remove_file(socket).unwrap();
     }
 }
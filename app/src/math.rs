@@ -2,13 +2,11 @@ use std::cmp::Ordering;
 
 use error_stack::{Result, ResultExt};
 use glam::{EulerRot, Quat, Vec3A};
-use nalgebra::base::Matrix4;
-use nalgebra::linalg::SVD;
-use nalgebra::DMatrix; // nalgebra can be used for SVD
+use nalgebra::{Matrix3, Matrix3x4, Matrix4, Vector3};
 use rust_3d::{IsNormalized3D, Line3D, Norm3D, Point3D};
 
 use crate::{
-    config::{CameraProperties, Config, Device},
+    config::{CameraProperties, Config, Device, Triangulation},
     error, GError, HasGlamPosition, HasGlamQuat, HasImagePosition, ImageCoords,
 };
 
@@ -55,7 +53,24 @@ impl Line {
     }
 }
 
+/// Position of a person from where both cameras see them.
 pub fn calc_position(
+    method: Triangulation,
+    camera1: &CameraProperties,
+    img_coords1: &ImageCoords,
+    camera2: &CameraProperties,
+    img_coords2: &ImageCoords,
+) -> Result<Vec3A, GError> {
+    let img_coords1 = &camera1.undistort(img_coords1);
+    let img_coords2 = &camera2.undistort(img_coords2);
+
+    match method {
+        Triangulation::Rays => intersect_rays(camera1, img_coords1, camera2, img_coords2),
+        Triangulation::Dlt => triangulation(camera1, img_coords1, camera2, img_coords2),
+    }
+}
+
+fn intersect_rays(
     camera1: &CameraProperties,
     img_coords1: &ImageCoords,
     camera2: &CameraProperties,
@@ -70,87 +85,104 @@ pub fn calc_position(
     line1.closest_point_bw(&line2)
 }
 
-fn triangulation(
+/// Triangulates from the calibration of both cameras, the position is in
+/// world coordinates like the one from the rays.
+pub fn triangulation(
     camera1: &CameraProperties,
     img_coords1: &ImageCoords,
     camera2: &CameraProperties,
     img_coords2: &ImageCoords,
-) -> Result<Vec3A, &'static str> {
+) -> Result<Vec3A, GError> {
     // Construct the projection matrices for both cameras
     let p1 = construct_projection_matrix(camera1);
     let p2 = construct_projection_matrix(camera2);
 
     // Call the DLT function to triangulate the point
-    let point_3d = dlt(&p1, &p2, img_coords1, img_coords2);
+    let point_3d = dlt(&p1, &p2, img_coords1, img_coords2)?;
 
-    Ok(point_3d)
+    Ok(calibration_to_world(camera1, &point_3d))
 }
 
-fn construct_projection_matrix(camera: &CameraProperties) -> [[f64; 4]; 3] {
+/// `K[R|t]`, taking points in the frame of the stereo calibration to pixels.
+pub fn construct_projection_matrix(camera: &CameraProperties) -> Matrix3x4<f64> {
     // Intrinsic matrix
-    let k = camera.intrensic_prams;
+    let k = matrix3(&camera.intrensic_prams);
 
     // Rotation matrix
     let r = camera.rotation_matrix;
 
     // Translation vector
-    let t = [
-        camera.pos_x as f64,
-        camera.pos_y as f64,
-        camera.pos_z as f64,
-    ];
+    let t = camera.translation_vector;
 
     // Concatenate the rotation matrix and translation vector to form the RT matrix
-    [
-        [r[0][0], r[0][1], r[0][2], t[0]],
-        [r[1][0], r[1][1], r[1][2], t[1]],
-        [r[2][0], r[2][1], r[2][2], t[2]],
-    ]
+    let rt = Matrix3x4::from_fn(|row, col| if col < 3 { r[row][col] } else { t[row] });
+
+    k * rt
 }
 
-fn dlt(
-    p1: &[[f64; 4]; 3],
-    p2: &[[f64; 4]; 3],
+/// Point in the frame of the stereo calibration seen at both pixels.
+pub fn dlt(
+    p1: &Matrix3x4<f64>,
+    p2: &Matrix3x4<f64>,
     point1: &ImageCoords,
     point2: &ImageCoords,
-) -> Vec3A {
+) -> Result<Vector3<f64>, GError> {
     // Create the A matrix for solving the linear system
-    let a = DMatrix::from_row_slice(
-        4,
-        4,
-        &[
-            point1.y as f64 * p1[2][0] - p1[1][0],
-            point1.y as f64 * p1[2][1] - p1[1][1],
-            point1.y as f64 * p1[2][2] - p1[1][2],
-            point1.y as f64 * p1[2][3] - p1[1][3],
-            p1[0][0] - point1.x as f64 * p1[2][0],
-            p1[0][1] - point1.x as f64 * p1[2][1],
-            p1[0][2] - point1.x as f64 * p1[2][2],
-            p1[0][3] - point1.x as f64 * p1[2][3],
-            point2.y as f64 * p2[2][0] - p2[1][0],
-            point2.y as f64 * p2[2][1] - p2[1][1],
-            point2.y as f64 * p2[2][2] - p2[1][2],
-            point2.y as f64 * p2[2][3] - p2[1][3],
-            p2[0][0] - point2.x as f64 * p2[2][0],
-            p2[0][1] - point2.x as f64 * p2[2][1],
-            p2[0][2] - point2.x as f64 * p2[2][2],
-            p2[0][3] - point2.x as f64 * p2[2][3],
-        ],
-    );
+    let rows = |p: &Matrix3x4<f64>, point: &ImageCoords| {
+        [
+            p.row(2) * point.y as f64 - p.row(1),
+            p.row(0) - p.row(2) * point.x as f64,
+        ]
+    };
+    let [a1, a2] = rows(p1, point1);
+    let [a3, a4] = rows(p2, point2);
+    let a = Matrix4::from_rows(&[a1, a2, a3, a4]);
+
+    // The solution is the right singular vector of the smallest singular value,
+    // normalized by its fourth component
+    let svd = a.svd(false, true);
+    let v_t = svd
+        .v_t
+        .ok_or(GError::MathError)
+        .attach_printable("SVD didn't compute V")?;
+    let v = v_t.row(svd.singular_values.imin());
+    if v[3].abs() < EPSILON as f64 {
+        return Err(GError::MathError).attach_printable("The triangulated point is at infinity");
+    }
 
-    // Perform SVD on A^T * A
-    let b = a.transpose() * a;
-    let svd = SVD::new(b, true, true);
+    Ok(Vector3::new(v[0] / v[3], v[1] / v[3], v[2] / v[3]))
+}
 
-    // The solution is the last row of V (or Vh), normalized by its fourth component
-    let v = svd.vt.unwrap();
-    let point_3d = Vec3A::new(
-        v[(3, 0)] as f32 / v[(3, 3)] as f32,
-        v[(3, 1)] as f32 / v[(3, 3)] as f32,
-        v[(3, 2)] as f32 / v[(3, 3)] as f32,
-    );
+/// Takes a point from the frame of the stereo calibration to world
+/// coordinates, through the pose camera 1 has in both.
+fn calibration_to_world(camera1: &CameraProperties, point: &Vector3<f64>) -> Vec3A {
+    let r = matrix3(&camera1.rotation_matrix);
+    let t = Vector3::from(camera1.translation_vector);
+    // x to the right of the image, y down and z along the optical axis
+    let p = r * point + t;
+
+    // the camera looks along x with y to the right of the image and z up
+    let local = Vec3A::new(p.z as f32, p.x as f32, -p.y as f32);
+    *camera1.pos() + camera1.quat().mul_vec3a(local)
+}
+
+pub fn matrix3(rows: &[[f64; 3]; 3]) -> Matrix3<f64> {
+    Matrix3::from_fn(|row, col| rows[row][col])
+}
 
-    point_3d
+pub fn to_rows(m: &Matrix3<f64>) -> [[f64; 3]; 3] {
+    [0, 1, 2].map(|row| [0, 1, 2].map(|col| m[(row, col)]))
+}
+
+/// `[t]x R` of a camera turned by `r` and moved by `t` from another one.
+pub fn essential_matrix(r: &Matrix3<f64>, t: &Vector3<f64>) -> Matrix3<f64> {
+    #[rustfmt::skip]
+    let t_cross = Matrix3::new(
+        0.0, -t.z, t.y,
+        t.z, 0.0, -t.x,
+        -t.y, t.x, 0.0,
+    );
+    t_cross * r
 }
 
 pub fn calc_pos_dir_vec(camera: &CameraProperties, coords: &ImageCoords) -> Vec3A {
@@ -289,6 +321,70 @@ mod tests {
     //     assert_eq!(Vec3A::new(0.86602485, 0.0, -0.5000011), res);
     // }
 
+    #[test]
+    fn triangulate_sample_points() {
+        // The expected points are what triangulate() of triangulation_DLT.py
+        // prints for its uvs1 and uvs2, with this calibration in stereoMap.xml
+        // (which isn't checked in): CameraMatrixL and CameraMatrixR are the
+        // intrensic_prams of test_new, RotationMatrix is the rotation_matrix
+        // of test_dir_vec and TranslationMatrix is [23.8, 0, 0.3], camera 2
+        // 23.8 units to the left of camera 1 as the pictures in testing/ show.
+        // Write those to stereoMap.xml and rerun the script to regenerate them.
+        let mut camera1 = CameraProperties::test_new();
+        camera1.pos_z = 10.0;
+        let mut camera2 = CameraProperties::test_new();
+        camera2.rotation_matrix = [
+            [
+                9.9165936444845415e-01,
+                8.3969100257135582e-02,
+                9.7779829738525781e-02,
+            ],
+            [
+                -8.9473106805596891e-02,
+                9.9456050000375096e-01,
+                5.3328932024213592e-02,
+            ],
+            [
+                -9.2769973915282689e-02,
+                -6.1632799987474653e-02,
+                9.9377820961493302e-01,
+            ],
+        ];
+        camera2.translation_vector = [23.8, 0.0, 0.3];
+        let p1 = construct_projection_matrix(&camera1);
+        let p2 = construct_projection_matrix(&camera2);
+
+        // uvs1, uvs2 and the triangulated points printed for them
+        let samples = [
+            (
+                [685.0, 210.0],
+                [866.0, 153.0],
+                Vector3::new(-13.863801871729228, -109.54465618783213, 592.8223418065963),
+            ),
+            (
+                [313.0, 307.0],
+                [473.0, 281.0],
+                Vector3::new(-507.09353793201274, -206.9257742353181, 1773.6257497200395),
+            ),
+        ];
+        for ([u1, v1], [u2, v2], expected) in samples {
+            let img1 = ImageCoords::new(u1, v1, 1296, 972);
+            let img2 = ImageCoords::new(u2, v2, 1296, 972);
+
+            let found = dlt(&p1, &p2, &img1, &img2).unwrap();
+            assert!((found - expected).norm() < 1e-6 * expected.norm());
+
+            // the world has camera 1 looking along x with z up
+            let world = triangulation(&camera1, &img1, &camera2, &img2).unwrap();
+            let expected = Vec3A::new(
+                expected.z as f32,
+                expected.x as f32,
+                10.0 - expected.y as f32,
+            );
+            assert!(world.abs_diff_eq(expected, 1e-3));
+        }
+    }
+
     #[test]
     fn test_find_yaw() {
         let mut camera1 = CameraProperties::test_new();
@@ -0,0 +1,173 @@
+//! Fake processes speaking the IPC protocol, so the orchestrator can be tested
+//! end to end without cameras or models.
+
+use std::{
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use serde_json::Value;
+
+use crate::protocol::{self, Header, Hello, MessageKind, PixelFormat};
+
+/// Two 64x48 cameras 20 units apart looking along x, with a device on either
+/// side of them. Gestures act on the first frame they are seen in.
+pub const CONFIG: &str = r#"
+    [camera1]
+    fov_x = 1.0
+    fov_y = 0.8
+    pos_x = 0
+    pos_y = 0
+    pos_z = 0
+    pitch = 0
+    yaw = 0
+    roll = 0
+    img_height = 48
+    img_width = 64
+    intrensic_prams = [[60, 0, 32], [0, 60, 24], [0, 0, 1]]
+    rotation_matrix = [[1, 0, 0], [0, 1, 0], [0, 0, 1]]
+
+    [camera2]
+    fov_x = 1.0
+    fov_y = 0.8
+    pos_x = 0
+    pos_y = 20
+    pos_z = 0
+    pitch = 0
+    yaw = 0
+    roll = 0
+    img_height = 48
+    img_width = 64
+    intrensic_prams = [[60, 0, 32], [0, 60, 24], [0, 0, 1]]
+    rotation_matrix = [[1, 0, 0], [0, 1, 0], [0, 0, 1]]
+
+    [[devices]]
+    name = "Lamp"
+    pin = 23
+    min_x = -12
+    min_y = -2
+    min_z = -2
+    max_x = -8
+    max_y = 2
+    max_z = 2
+
+    [[devices]]
+    name = "Fan"
+    pin = 27
+    min_x = 98
+    min_y = -52
+    min_z = -2
+    max_x = 102
+    max_y = -48
+    max_z = 2
+
+    [filter]
+    window = 1
+    votes = 1
+    cooldown = 0"#;
+
+/// Socket path unique to a test, removed if a previous run left it behind.
+pub fn socket_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("gesture-ease-{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Connects as a process of the given kind and completes the handshake.
+pub fn connect(socket: &Path, process: &str) -> UnixStream {
+    let stream = UnixStream::connect(socket).unwrap();
+
+    let hello = serde_json::to_vec(&Hello {
+        process: process.into(),
+        version: "mock".into(),
+        pixel_formats: vec![PixelFormat::Rgb888],
+        model: Some("mock".into()),
+        max_batch: 1,
+    })
+    .unwrap();
+    protocol::write_frame(
+        &stream,
+        &Header::new(MessageKind::Hello, 0, hello.len()),
+        &hello,
+    )
+    .unwrap();
+
+    let (header, _) = protocol::read_frame(&stream).unwrap();
+    header.expect_kind(MessageKind::Hello).unwrap();
+
+    stream
+}
+
+/// Model process answering each image with the next scripted prediction, the
+/// frame id is filled in. Hangs up once the script runs out.
+pub fn model(socket: &Path, process: &str, replies: Vec<Value>) -> JoinHandle<()> {
+    slow_model(socket, process, replies, Duration::ZERO)
+}
+
+/// Like [`model`], but takes `delay` to answer each image.
+pub fn slow_model(
+    socket: &Path,
+    process: &str,
+    replies: Vec<Value>,
+    delay: Duration,
+) -> JoinHandle<()> {
+    let socket = socket.to_owned();
+    let process = process.to_owned();
+
+    thread::spawn(move || {
+        let stream = connect(&socket, &process);
+
+        for mut reply in replies {
+            let header = match protocol::read_frame(&stream) {
+                Ok((header, _)) => header,
+                Err(_) => return,
+            };
+            header.expect_kind(MessageKind::Image).unwrap();
+            thread::sleep(delay);
+
+            reply["frame"] = header.seq.into();
+            let msg = serde_json::to_vec(&reply).unwrap();
+            protocol::write_frame(
+                &stream,
+                &Header::new(MessageKind::Prediction, header.seq, msg.len()),
+                &msg,
+            )
+            .unwrap();
+        }
+    })
+}
+
+/// Camera process answering every capture with blank images of the configured size.
+pub fn camera(socket: &Path) -> JoinHandle<()> {
+    let socket = socket.to_owned();
+
+    thread::spawn(move || {
+        let stream = connect(&socket, "cam");
+
+        let mut sizes = vec![];
+        for _ in 0..2 {
+            let (header, _) = protocol::read_frame(&stream).unwrap();
+            header.expect_kind(MessageKind::Configure).unwrap();
+            sizes.push((header.width, header.height));
+        }
+
+        while let Ok((header, _)) = protocol::read_frame(&stream) {
+            header.expect_kind(MessageKind::Capture).unwrap();
+
+            for &(w, h) in &sizes {
+                let img = vec![0; (w * h * 3) as usize];
+                let reply = Header::new(MessageKind::Image, header.seq, img.len()).with_image(
+                    PixelFormat::Rgb888,
+                    w,
+                    h,
+                );
+                if protocol::write_frame(&stream, &reply, &img).is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
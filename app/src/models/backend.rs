@@ -0,0 +1,100 @@
+use std::{
+    os::unix::net::UnixStream,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use error_stack::{Report, Result, ResultExt};
+use flume::{unbounded, Receiver, Sender};
+
+use super::ModelResponse;
+use crate::{
+    protocol::{FrameId, MessageKind},
+    traits::{Reconnect, WantIpc},
+    GError,
+};
+
+/// Something that can run a model on a raw rgb image.
+pub trait InferenceBackend<R>: Send + 'static {
+    fn infer(&mut self, frame: FrameId, img: &[u8], w: u32, h: u32) -> Result<R, GError>;
+}
+
+/// Runs the model in a separate process reached over the unix socket.
+#[derive(Clone)]
+pub struct IpcBackend {
+    stream_sender: Sender<UnixStream>,
+    stream_receiver: Receiver<UnixStream>,
+    unix_stream: Arc<UnixStream>,
+    live: Arc<AtomicBool>,
+}
+
+impl IpcBackend {
+    pub fn new(unix_stream: UnixStream) -> Self {
+        let (stream_sender, stream_receiver) = unbounded();
+
+        Self {
+            stream_sender,
+            stream_receiver,
+            unix_stream: Arc::new(unix_stream),
+            live: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+impl<R: ModelResponse> InferenceBackend<R> for IpcBackend {
+    fn infer(&mut self, frame: FrameId, img: &[u8], w: u32, h: u32) -> Result<R, GError> {
+        if self.reconnect() {
+            println!("{} model reconnected", R::NAME);
+        }
+        if !self.is_up() {
+            return Err(Report::new(GError::WorkerDown)
+                .attach_printable(format!("{} model is not connected", R::NAME)));
+        }
+
+        let res = self
+            .send_ipc(frame, img, w, h)
+            .and_then(|_| self.recv_ipc(MessageKind::Prediction, frame));
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                println!("{} model disconnected", R::NAME);
+                self.mark_down();
+                return Err(e);
+            }
+        };
+
+        let res = serde_json::from_slice::<R>(&res).change_context(GError::IpcError)?;
+        if res.frame() != frame {
+            return Err(GError::ProtocolError).attach_printable(format!(
+                "Prediction for frame {} sent as response to frame {}",
+                res.frame(),
+                frame
+            ));
+        }
+
+        Ok(res)
+    }
+}
+
+impl WantIpc for IpcBackend {
+    fn unix_stream(&self) -> &UnixStream {
+        &self.unix_stream
+    }
+}
+
+impl Reconnect for IpcBackend {
+    fn stream_sender(&self) -> &Sender<UnixStream> {
+        &self.stream_sender
+    }
+
+    fn stream_receiver(&self) -> &Receiver<UnixStream> {
+        &self.stream_receiver
+    }
+
+    fn liveness(&self) -> &AtomicBool {
+        &self.live
+    }
+
+    fn set_unix_stream(&mut self, stream: UnixStream) {
+        self.unix_stream = Arc::new(stream);
+    }
+}
@@ -1,119 +1,148 @@
 use std::{
     ops::{Deref, DerefMut},
-    os::unix::net::UnixStream,
-    sync::Arc,
-    thread::{self, JoinHandle},
+    str::FromStr,
 };
 
-use error_stack::Result;
-use flume::{unbounded, Receiver, Sender};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::{
-    traits::{Responder, WantIpc},
-    GError, HasImagePosition, ImageProcessor,
-};
-
-#[derive(Clone)]
-pub struct GestureDetection {
-    image_sender: Sender<(u32, u32, Arc<[u8]>)>,
-    image_receiver: Receiver<(u32, u32, Arc<[u8]>)>,
-    response_sender: Sender<GesturePreds>,
-    response_receiver: Receiver<GesturePreds>,
-    unix_stream: Arc<UnixStream>,
-}
-
-impl GestureDetection {
-    pub fn new(unix_stream: UnixStream) -> Self {
-        let (image_sender, image_receiver) = unbounded();
-        let (response_sender, response_receiver) = unbounded();
-        let unix_stream = Arc::new(unix_stream);
-
-        Self {
-            image_sender,
-            image_receiver,
-            response_sender,
-            response_receiver,
-            unix_stream,
-        }
-    }
+#[cfg(feature = "onnx")]
+use error_stack::{Result, ResultExt};
 
-    pub fn run(&self) -> JoinHandle<()> {
-        let instance = self.clone();
-        println!("Gesture Detection model connected");
+#[cfg(feature = "onnx")]
+use super::OnnxDecode;
+use super::{ModelResponse, ModelWorker};
+#[cfg(feature = "onnx")]
+use crate::{config::OnnxConfig, GError};
+use crate::{protocol::FrameId, tracker::TrackId, HasImagePosition, HasTrackId};
 
-        thread::spawn(move || loop {
-            let (w, h, _img) = instance.recv_img().unwrap();
+pub type GestureDetection = ModelWorker<GesturePreds>;
 
-            instance.send_ipc(&_img, w, h).unwrap();
-            let res = instance.recv_ipc().unwrap();
-            let res: GesturePreds = serde_json::from_slice(&res).unwrap();
+#[derive(Default, Debug, Deserialize, Serialize, Clone)]
+pub struct GesturePreds {
+    /// Frame the predictions were made on, echoed back by the process.
+    pub frame: FrameId,
+    pub prediction: Vec<GesturePrediction>,
+}
 
-            instance.send_response(res).unwrap();
-        })
-    }
+impl Deref for GesturePreds {
+    type Target = Vec<GesturePrediction>;
 
-    pub fn send(&self, img: Arc<[u8]>, w: u32, h: u32) -> Result<(), GError> {
-        self.send_img(img, w, h)
+    fn deref(&self) -> &Self::Target {
+        &self.prediction
     }
+}
 
-    pub fn recv(&self) -> Result<GesturePreds, GError> {
-        self.recv_response()
+impl DerefMut for GesturePreds {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.prediction
     }
 }
 
-impl ImageProcessor for GestureDetection {
-    fn image_sender(&self) -> &Sender<(u32, u32, Arc<[u8]>)> {
-        &self.image_sender
-    }
+impl ModelResponse for GesturePreds {
+    const NAME: &'static str = "Gesture Detection";
 
-    fn image_receiver(&self) -> &Receiver<(u32, u32, Arc<[u8]>)> {
-        &self.image_receiver
+    fn frame(&self) -> FrameId {
+        self.frame
     }
 }
 
-impl Responder for GestureDetection {
-    type Response = GesturePreds;
+/// Rows are `[nose_x, nose_y, score for each of config.labels.., value]` with the
+/// position normalized to the image size, the value column is optional.
+#[cfg(feature = "onnx")]
+impl OnnxDecode for GesturePreds {
+    fn decode(
+        frame: FrameId,
+        rows: Vec<&[f32]>,
+        config: &OnnxConfig,
+        w: u32,
+        h: u32,
+    ) -> Result<Self, GError> {
+        if config.labels.is_empty() {
+            return Err(GError::ConfigError).attach_printable("Gesture model needs labels");
+        }
 
-    fn response_sender(&self) -> &Sender<Self::Response> {
-        &self.response_sender
-    }
+        let prediction = rows
+            .into_iter()
+            .filter(|row| row.len() >= 2 + config.labels.len())
+            .map(|row| {
+                let best = row[2..2 + config.labels.len()]
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .filter(|(_, score)| **score >= config.threshold);
 
-    fn response_receiver(&self) -> &Receiver<Self::Response> {
-        &self.response_receiver
-    }
-}
+                let gesture = best
+                    .and_then(|(i, _)| config.labels[i].parse().ok())
+                    .unwrap_or_default();
 
-impl WantIpc for GestureDetection {
-    fn unix_stream(&self) -> &UnixStream {
-        &self.unix_stream
+                GesturePrediction {
+                    nose_x: row[0] * w as f32,
+                    nose_y: row[1] * h as f32,
+                    gesture,
+                    value: row.get(2 + config.labels.len()).copied(),
+                    id: None,
+                }
+            })
+            .collect();
+
+        Ok(Self { frame, prediction })
     }
 }
 
-#[derive(Default, Debug, Deserialize, Clone)]
-pub struct GesturePreds {
-    pub prediction: Vec<GesturePrediction>,
+/// Read from the labels of the gesture model, in any case and with or without
+/// separators, so `brightness_up`, `Brightness Up` and `BrightnessUp` all work.
+#[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub enum Gesture {
+    Toggle,
+    On,
+    Off,
+    BrightnessUp,
+    BrightnessDown,
+    Next,
+    Previous,
+    Point,
+    Cancel,
+    SwipeLeft,
+    SwipeRight,
+    #[default]
+    None,
 }
 
-impl Deref for GesturePreds {
-    type Target = Vec<GesturePrediction>;
+impl FromStr for Gesture {
+    type Err = String;
 
-    fn deref(&self) -> &Self::Target {
-        &self.prediction
-    }
-}
+    fn from_str(label: &str) -> std::result::Result<Self, Self::Err> {
+        let name: String = label
+            .chars()
+            .filter(|c| !matches!(c, '_' | '-' | ' '))
+            .flat_map(char::to_lowercase)
+            .collect();
 
-impl DerefMut for GesturePreds {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.prediction
+        Ok(match name.as_str() {
+            "toggle" => Self::Toggle,
+            "on" => Self::On,
+            "off" => Self::Off,
+            "brightnessup" => Self::BrightnessUp,
+            "brightnessdown" => Self::BrightnessDown,
+            "next" => Self::Next,
+            "previous" | "prev" => Self::Previous,
+            "point" => Self::Point,
+            "cancel" => Self::Cancel,
+            "swipeleft" => Self::SwipeLeft,
+            "swiperight" => Self::SwipeRight,
+            "none" | "" => Self::None,
+            _ => return Err(format!("unknown gesture {}", label)),
+        })
     }
 }
 
-#[derive(Default, Debug, Deserialize, Clone, PartialEq, Eq)]
-pub enum Gesture {
-    Toggle,
-    #[default]
-    None,
+impl TryFrom<String> for Gesture {
+    type Error = String;
+
+    fn try_from(label: String) -> std::result::Result<Self, Self::Error> {
+        label.parse()
+    }
 }
 
 impl Gesture {
@@ -132,11 +161,32 @@ impl Gesture {
     }
 }
 
-#[derive(Default, Debug, Deserialize, Clone, PartialEq)]
+#[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct GesturePrediction {
     pub nose_x: f32,
     pub nose_y: f32,
+    #[serde(deserialize_with = "lenient_gesture")]
     pub gesture: Gesture,
+    /// Continuous reading of the gesture from 0 to 1, like how high the hand
+    /// is or how far it's turned, for gestures that set a level.
+    #[serde(default)]
+    pub value: Option<f32>,
+    /// Set by the tracker of camera 1.
+    #[serde(default)]
+    pub id: Option<TrackId>,
+}
+
+/// A label the model knows but we don't is no gesture, rather than failing the
+/// predictions for everyone in the frame.
+fn lenient_gesture<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Gesture, D::Error> {
+    let label = String::deserialize(deserializer)?;
+
+    Ok(label.parse().unwrap_or_else(|e| {
+        println!("Taking {} for no gesture", e);
+        Gesture::None
+    }))
 }
 
 impl Deref for GesturePrediction {
@@ -147,6 +197,16 @@ impl Deref for GesturePrediction {
     }
 }
 
+impl HasTrackId for GesturePrediction {
+    fn track_id(&self) -> Option<TrackId> {
+        self.id
+    }
+
+    fn set_track_id(&mut self, id: TrackId) {
+        self.id = Some(id);
+    }
+}
+
 impl HasImagePosition for GesturePrediction {
     fn image_x(&self) -> f32 {
         self.nose_x
@@ -156,3 +216,44 @@ impl HasImagePosition for GesturePrediction {
         self.nose_y
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_labels() {
+        let preds: GesturePreds = serde_json::from_str(
+            r#"{"frame": 3, "prediction": [
+                {"nose_x": 1.0, "nose_y": 2.0, "gesture": "brightness_up", "value": 0.5},
+                {"nose_x": 1.0, "nose_y": 2.0, "gesture": "Swipe Left"},
+                {"nose_x": 1.0, "nose_y": 2.0, "gesture": "Toggle"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let gestures: Vec<_> = preds.iter().map(|p| p.gesture.clone()).collect();
+        assert_eq!(
+            gestures,
+            [Gesture::BrightnessUp, Gesture::SwipeLeft, Gesture::Toggle]
+        );
+        assert_eq!(preds[0].value, Some(0.5));
+        assert_eq!(preds[1].value, None);
+        assert!("wave".parse::<Gesture>().is_err());
+    }
+
+    #[test]
+    fn unknown_label_is_none() {
+        let preds: GesturePreds = serde_json::from_str(
+            r#"{"frame": 4, "prediction": [
+                {"nose_x": 1.0, "nose_y": 2.0, "gesture": "Two_Hands_Up"},
+                {"nose_x": 3.0, "nose_y": 4.0, "gesture": "Toggle"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(preds[0].gesture, Gesture::None);
+        assert_eq!(preds[0].nose_x, 1.0);
+        assert_eq!(preds[1].gesture, Gesture::Toggle);
+    }
+}
@@ -1,97 +1,23 @@
-use std::{
-    ops::{Deref, DerefMut},
-    os::unix::net::UnixStream,
-    sync::Arc,
-    thread::{self, JoinHandle},
-};
+use std::ops::{Deref, DerefMut};
 
-use error_stack::Result;
-use flume::{unbounded, Receiver, Sender};
-use serde::Deserialize;
-
-use crate::{
-    traits::{Responder, WantIpc},
-    GError, HasImagePosition, ImageProcessor,
-};
-
-#[derive(Clone)]
-pub struct HeadDetection {
-    image_sender: Sender<(u32, u32, Arc<[u8]>)>,
-    image_receiver: Receiver<(u32, u32, Arc<[u8]>)>,
-    response_sender: Sender<HeadPreds>,
-    response_receiver: Receiver<HeadPreds>,
-    unix_stream: Arc<UnixStream>,
-}
-
-impl HeadDetection {
-    pub fn new(unix_stream: UnixStream) -> Self {
-        let (image_sender, image_receiver) = unbounded();
-        let (response_sender, response_receiver) = unbounded();
-        let unix_stream = Arc::new(unix_stream);
-
-        Self {
-            image_sender,
-            image_receiver,
-            response_sender,
-            response_receiver,
-            unix_stream,
-        }
-    }
-
-    pub fn run(&self) -> JoinHandle<()> {
-        let instance = self.clone();
-        println!("Head Detection model connected");
-
-        thread::spawn(move || loop {
-            let (w, h, _img) = instance.recv_img().unwrap();
-
-            instance.send_ipc(&_img, w, h).unwrap();
-            let res = instance.recv_ipc().unwrap();
-            let res: HeadPreds = serde_json::from_slice(&res).unwrap();
-
-            instance.send_response(res).unwrap();
-        })
-    }
-
-    pub fn send(&self, img: Arc<[u8]>, w: u32, h: u32) -> Result<(), GError> {
-        self.send_img(img, w, h)
-    }
-
-    pub fn recv(&self) -> Result<HeadPreds, GError> {
-        self.recv_response()
-    }
-}
-
-impl ImageProcessor for HeadDetection {
-    fn image_sender(&self) -> &Sender<(u32, u32, Arc<[u8]>)> {
-        &self.image_sender
-    }
-
-    fn image_receiver(&self) -> &Receiver<(u32, u32, Arc<[u8]>)> {
-        &self.image_receiver
-    }
-}
+use serde::{Deserialize, Serialize};
 
-impl Responder for HeadDetection {
-    type Response = HeadPreds;
-
-    fn response_sender(&self) -> &Sender<Self::Response> {
-        &self.response_sender
-    }
+#[cfg(feature = "onnx")]
+use error_stack::Result;
 
-    fn response_receiver(&self) -> &Receiver<Self::Response> {
-        &self.response_receiver
-    }
-}
+#[cfg(feature = "onnx")]
+use super::OnnxDecode;
+use super::{ModelResponse, ModelWorker};
+#[cfg(feature = "onnx")]
+use crate::{config::OnnxConfig, GError};
+use crate::{protocol::FrameId, tracker::TrackId, HasImagePosition, HasTrackId};
 
-impl WantIpc for HeadDetection {
-    fn unix_stream(&self) -> &UnixStream {
-        &self.unix_stream
-    }
-}
+pub type HeadDetection = ModelWorker<HeadPreds>;
 
-#[derive(Default, Debug, Deserialize)]
+#[derive(Default, Debug, Deserialize, Serialize)]
 pub struct HeadPreds {
+    /// Frame the predictions were made on, echoed back by the process.
+    pub frame: FrameId,
     pub prediction: Vec<HeadPrediction>,
 }
 
@@ -109,10 +35,45 @@ impl DerefMut for HeadPreds {
     }
 }
 
-#[derive(Default, Debug, Deserialize)]
+impl ModelResponse for HeadPreds {
+    const NAME: &'static str = "Head Detection";
+
+    fn frame(&self) -> FrameId {
+        self.frame
+    }
+}
+
+/// Rows are `[nose_x, nose_y, score]` with the position normalized to the image size.
+#[cfg(feature = "onnx")]
+impl OnnxDecode for HeadPreds {
+    fn decode(
+        frame: FrameId,
+        rows: Vec<&[f32]>,
+        config: &OnnxConfig,
+        w: u32,
+        h: u32,
+    ) -> Result<Self, GError> {
+        let prediction = rows
+            .into_iter()
+            .filter(|row| row.len() >= 3 && row[2] >= config.threshold)
+            .map(|row| HeadPrediction {
+                nose_x: row[0] * w as f32,
+                nose_y: row[1] * h as f32,
+                id: None,
+            })
+            .collect();
+
+        Ok(Self { frame, prediction })
+    }
+}
+
+#[derive(Default, Debug, Deserialize, Serialize)]
 pub struct HeadPrediction {
     pub nose_x: f32,
     pub nose_y: f32,
+    /// Set by the tracker of camera 2.
+    #[serde(default)]
+    pub id: Option<TrackId>,
 }
 
 impl HasImagePosition for HeadPrediction {
@@ -124,3 +85,13 @@ impl HasImagePosition for HeadPrediction {
         self.nose_x
     }
 }
+
+impl HasTrackId for HeadPrediction {
+    fn track_id(&self) -> Option<TrackId> {
+        self.id
+    }
+
+    fn set_track_id(&mut self, id: TrackId) {
+        self.id = Some(id);
+    }
+}
@@ -1,7 +1,35 @@
+mod backend;
 mod gesture_recognition;
 mod head_detection;
 mod hpe;
+#[cfg(feature = "onnx")]
+mod onnx;
+mod worker;
 
+use error_stack::Result;
+#[cfg(not(feature = "onnx"))]
+use error_stack::ResultExt;
+
+use crate::{config::OnnxConfig, GError};
+
+pub use backend::{InferenceBackend, IpcBackend};
 pub use gesture_recognition::{Gesture, GestureDetection, GesturePrediction, GesturePreds};
 pub use head_detection::{HeadDetection, HeadPrediction, HeadPreds};
 pub use hpe::{HPEPreds, HeadPoseEstimation, HpePrediction};
+#[cfg(feature = "onnx")]
+pub use onnx::{OnnxBackend, OnnxDecode};
+pub use worker::{ModelResponse, ModelWorker};
+
+/// Creates a worker running the model in this process.
+#[cfg(feature = "onnx")]
+pub(crate) fn local_worker<R: OnnxDecode>(config: &OnnxConfig) -> Result<ModelWorker<R>, GError> {
+    Ok(ModelWorker::local(OnnxBackend::<R>::open(config)?))
+}
+
+#[cfg(not(feature = "onnx"))]
+pub(crate) fn local_worker<R: ModelResponse>(
+    _config: &OnnxConfig,
+) -> Result<ModelWorker<R>, GError> {
+    Err(GError::ConfigError)
+        .attach_printable("Built without the onnx feature, run the model as a separate process")
+}
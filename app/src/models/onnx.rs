@@ -0,0 +1,95 @@
+use std::marker::PhantomData;
+
+use error_stack::{Report, Result};
+use tract_onnx::prelude::*;
+
+use super::{InferenceBackend, ModelResponse};
+use crate::{config::OnnxConfig, protocol::FrameId, GError};
+
+/// Predictions that can be read from the output rows of an onnx model.
+pub trait OnnxDecode: ModelResponse {
+    fn decode(
+        frame: FrameId,
+        rows: Vec<&[f32]>,
+        config: &OnnxConfig,
+        w: u32,
+        h: u32,
+    ) -> Result<Self, GError>;
+}
+
+/// Runs an onnx model in this process with tract.
+pub struct OnnxBackend<R> {
+    model: Arc<TypedRunnableModel>,
+    config: OnnxConfig,
+    _response: PhantomData<fn() -> R>,
+}
+
+impl<R> OnnxBackend<R> {
+    pub fn open(config: &OnnxConfig) -> Result<Self, GError> {
+        let (w, h) = (config.input_width as usize, config.input_height as usize);
+
+        let model = tract_onnx::onnx()
+            .model_for_path(&config.path)
+            .and_then(|model| model.with_input_fact(0, f32::fact([1, 3, h, w]).into()))
+            .and_then(|model| model.into_optimized())
+            .and_then(|model| model.into_runnable())
+            .map_err(|e| {
+                Report::new(GError::ConfigError).attach_printable(format!(
+                    "Couldn't load model {}: {}",
+                    config.path.display(),
+                    e
+                ))
+            })?;
+
+        Ok(Self {
+            model,
+            config: config.clone(),
+            _response: PhantomData,
+        })
+    }
+}
+
+impl<R: OnnxDecode> InferenceBackend<R> for OnnxBackend<R> {
+    fn infer(&mut self, frame: FrameId, img: &[u8], w: u32, h: u32) -> Result<R, GError> {
+        let (w, h) = (w as usize, h as usize);
+        if img.len() < w * h * 3 {
+            return Err(
+                Report::new(GError::InferenceError).attach_printable(format!(
+                    "Expected a {}x{} rgb image, got {} bytes",
+                    w,
+                    h,
+                    img.len()
+                )),
+            );
+        }
+
+        let (iw, ih) = (
+            self.config.input_width as usize,
+            self.config.input_height as usize,
+        );
+        // nearest neighbour resize to the model input
+        let input: Tensor = tract_ndarray::Array4::from_shape_fn((1, 3, ih, iw), |(_, c, y, x)| {
+            let (sx, sy) = (x * w / iw, y * h / ih);
+            img[(sy * w + sx) * 3 + c] as f32 / 255.0
+        })
+        .into();
+
+        let outputs = self
+            .model
+            .run(tvec!(input.into()))
+            .map_err(|e| Report::new(GError::InferenceError).attach_printable(e.to_string()))?;
+        let output = outputs[0]
+            .to_plain_array_view::<f32>()
+            .map_err(|e| Report::new(GError::InferenceError).attach_printable(e.to_string()))?;
+
+        let cols = output.shape().last().copied().unwrap_or(0);
+        let data: Vec<f32> = output.iter().copied().collect();
+        let rows = if cols == 0 {
+            vec![]
+        } else {
+            data.chunks(cols).collect()
+        };
+
+        R::decode(frame, rows, &self.config, w as u32, h as u32)
+    }
+}
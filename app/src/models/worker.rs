@@ -0,0 +1,206 @@
+use std::{
+    marker::PhantomData,
+    os::unix::net::UnixStream,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use error_stack::{Report, Result};
+use flume::{unbounded, Receiver, Sender};
+use serde::de::DeserializeOwned;
+
+use super::{InferenceBackend, IpcBackend};
+use crate::{
+    protocol::FrameId,
+    traits::{ImageRequest, Reconnect, Responder},
+    GError, ImageProcessor,
+};
+
+/// Predictions a model process answers an image with.
+pub trait ModelResponse: DeserializeOwned + Send + 'static {
+    /// Name of the model, used in logs.
+    const NAME: &'static str;
+
+    /// Frame the predictions were made on, as echoed back by the process.
+    fn frame(&self) -> FrameId;
+}
+
+/// Queues images for a model and hands its predictions back. The model itself
+/// runs on an [`InferenceBackend`] in a thread of its own.
+pub struct ModelWorker<R> {
+    image_sender: Sender<ImageRequest>,
+    image_receiver: Receiver<ImageRequest>,
+    response_sender: Sender<(FrameId, Result<R, GError>)>,
+    response_receiver: Receiver<(FrameId, Result<R, GError>)>,
+    ipc: Option<IpcBackend>,
+    _response: PhantomData<fn() -> R>,
+}
+
+// derive would require R: Clone
+impl<R> Clone for ModelWorker<R> {
+    fn clone(&self) -> Self {
+        Self {
+            image_sender: self.image_sender.clone(),
+            image_receiver: self.image_receiver.clone(),
+            response_sender: self.response_sender.clone(),
+            response_receiver: self.response_receiver.clone(),
+            ipc: self.ipc.clone(),
+            _response: PhantomData,
+        }
+    }
+}
+
+impl<R: ModelResponse> ModelWorker<R> {
+    /// A model running in a separate process connected over `unix_stream`.
+    pub fn new(unix_stream: UnixStream) -> Self {
+        Self::with_ipc(Some(IpcBackend::new(unix_stream)))
+    }
+
+    /// A model running in this process, the worker thread is started right away.
+    pub fn local<B: InferenceBackend<R>>(backend: B) -> Self {
+        let model = Self::with_ipc(None);
+        println!("{} model loaded", R::NAME);
+        model.spawn(backend);
+        model
+    }
+
+    fn with_ipc(ipc: Option<IpcBackend>) -> Self {
+        let (image_sender, image_receiver) = unbounded();
+        let (response_sender, response_receiver) = unbounded();
+
+        Self {
+            image_sender,
+            image_receiver,
+            response_sender,
+            response_receiver,
+            ipc,
+            _response: PhantomData,
+        }
+    }
+
+    pub fn run(&self) -> JoinHandle<()> {
+        let ipc = self
+            .ipc
+            .clone()
+            .expect("local models are started on creation");
+        println!("{} model connected", R::NAME);
+
+        self.spawn(ipc)
+    }
+
+    fn spawn<B: InferenceBackend<R>>(&self, mut backend: B) -> JoinHandle<()> {
+        let instance = self.clone();
+
+        thread::spawn(move || loop {
+            let request = instance.recv_img().unwrap();
+            // whoever waited for older frames has given up by now, a slow model
+            // would only fall further behind answering them
+            let (frame, w, h, _img) =
+                instance
+                    .image_receiver()
+                    .try_iter()
+                    .fold(request, |(skipped, ..), newer| {
+                        let err = Report::new(GError::Timeout)
+                            .attach_printable(format!("Skipped for frame {}", newer.0));
+                        instance.send_error(skipped, err).unwrap();
+                        newer
+                    });
+
+            match backend.infer(frame, &_img, w, h) {
+                Ok(res) => instance.send_response(frame, res).unwrap(),
+                Err(e) => instance.send_error(frame, e).unwrap(),
+            }
+        })
+    }
+
+    /// Connection to the model process, none if the model runs in this process.
+    pub(crate) fn ipc(&self) -> Option<&IpcBackend> {
+        self.ipc.as_ref()
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.ipc.as_ref().is_none_or(|ipc| ipc.is_up())
+    }
+
+    pub fn send(&self, frame: FrameId, img: Arc<[u8]>, w: u32, h: u32) -> Result<(), GError> {
+        self.send_img(frame, img, w, h)
+    }
+
+    pub fn recv(&self, frame: FrameId) -> Result<R, GError> {
+        self.recv_response(frame)
+    }
+
+    pub fn recv_timeout(&self, frame: FrameId, timeout: Duration) -> Result<R, GError> {
+        self.recv_response_timeout(frame, timeout)
+    }
+}
+
+impl<R> ImageProcessor for ModelWorker<R> {
+    fn image_sender(&self) -> &Sender<ImageRequest> {
+        &self.image_sender
+    }
+
+    fn image_receiver(&self) -> &Receiver<ImageRequest> {
+        &self.image_receiver
+    }
+}
+
+impl<R> Responder for ModelWorker<R> {
+    type Response = R;
+
+    fn response_sender(&self) -> &Sender<(FrameId, Result<Self::Response, GError>)> {
+        &self.response_sender
+    }
+
+    fn response_receiver(&self) -> &Receiver<(FrameId, Result<Self::Response, GError>)> {
+        &self.response_receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{HeadPrediction, HeadPreds};
+
+    /// Finds a head in the middle of every image that isn't blank.
+    struct CenterHead;
+
+    impl InferenceBackend<HeadPreds> for CenterHead {
+        fn infer(
+            &mut self,
+            frame: FrameId,
+            img: &[u8],
+            w: u32,
+            h: u32,
+        ) -> Result<HeadPreds, GError> {
+            if img.iter().all(|px| *px == 0) {
+                return Err(Report::new(GError::InferenceError));
+            }
+            Ok(HeadPreds {
+                frame,
+                prediction: vec![HeadPrediction {
+                    nose_x: w as f32 / 2.0,
+                    nose_y: h as f32 / 2.0,
+                    id: None,
+                }],
+            })
+        }
+    }
+
+    #[test]
+    fn run_local_backend() {
+        let model = ModelWorker::local(CenterHead);
+        assert!(model.is_up());
+        assert!(model.ipc().is_none());
+
+        model.send(1, vec![0; 4 * 2 * 3].into(), 4, 2).unwrap();
+        let err = model.recv(1).unwrap_err();
+        assert!(matches!(err.current_context(), GError::InferenceError));
+
+        model.send(2, vec![255; 4 * 2 * 3].into(), 4, 2).unwrap();
+        let preds = model.recv_timeout(2, Duration::from_secs(1)).unwrap();
+        assert_eq!(preds.frame, 2);
+        assert_eq!((preds[0].nose_x, preds[0].nose_y), (2.0, 1.0));
+    }
+}
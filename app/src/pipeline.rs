@@ -0,0 +1,447 @@
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use error_stack::Result;
+
+use crate::{
+    config::{Action, Config, Device, Trigger},
+    epipolar::EpipolarMatcher,
+    filter::GestureFilter,
+    math::{
+        angle_bw_cameras_from_z_axis, calc_position, get_closest_device_in_los_alt, get_los,
+        sort_align,
+    },
+    models::{GesturePrediction, HeadPrediction, HpePrediction},
+    protocol::FrameId,
+    sequence::SequenceRecognizer,
+    session::Record,
+    tracker::{self, TrackId, Tracker},
+    GError, HasGlamQuat, HasImagePosition, HasTrackId, Models, Process,
+};
+
+/// What was computed for one frame pair and the devices it asks to act on.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    pub record: Record,
+    /// Devices new gestures and finished sequences were aimed at.
+    pub targets: Vec<Target>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub device: Device,
+    pub trigger: Trigger,
+    /// Reading of the gesture, for actions setting a level.
+    pub value: Option<f32>,
+    /// Track id of the person in camera 1.
+    pub person: Option<TrackId>,
+}
+
+/// Runs the models on frame pairs and works out which device each gesture is aimed at.
+pub struct Pipeline<'a> {
+    config: &'a Config,
+    theta: f32,
+    /// Pairs new people across the cameras when they're calibrated.
+    epipolar: Option<EpipolarMatcher>,
+    /// People seen by camera 1, making gestures, and camera 2.
+    cam1: Tracker,
+    cam2: Tracker,
+    /// Person in camera 2 for each person in camera 1.
+    pairs: HashMap<TrackId, TrackId>,
+    filter: GestureFilter,
+    sequences: SequenceRecognizer,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            theta: angle_bw_cameras_from_z_axis(&config.camera1, &config.camera2),
+            epipolar: config
+                .stereo
+                .as_ref()
+                .and_then(|stereo| EpipolarMatcher::new(stereo, &config.camera1, &config.camera2)),
+            cam1: Tracker::new(&config.tracker),
+            cam2: Tracker::new(&config.tracker),
+            pairs: HashMap::new(),
+            filter: GestureFilter::new(&config.filter),
+            sequences: SequenceRecognizer::new(&config.sequences),
+        }
+    }
+
+    pub fn process(
+        &mut self,
+        models: &Models,
+        frame: FrameId,
+        frame1: Arc<[u8]>,
+        frame2: Arc<[u8]>,
+    ) -> Result<Outcome, GError> {
+        let config = self.config;
+
+        // send frame1 to gesture detection model
+        models.gesture()?.send(
+            frame,
+            frame1.clone(),
+            config.camera1.img_width,
+            config.camera1.img_height,
+        )?;
+        // send frame2 to head detection model
+        models.head_detection()?.send(
+            frame,
+            frame2,
+            config.camera2.img_width,
+            config.camera2.img_height,
+        )?;
+
+        let mut head_positions = models
+            .head_detection()?
+            .recv_timeout(frame, config.timeouts.get(Process::HeadDetection))?;
+        let mut gestures = models
+            .gesture()?
+            .recv_timeout(frame, config.timeouts.get(Process::GestureRecognition))?;
+
+        let mut outcome = Outcome::default();
+        outcome.record.frame = frame;
+
+        let now = Instant::now();
+        self.cam1.update(&mut gestures, now);
+        self.cam2.update(&mut head_positions, now);
+
+        let steady = self.filter.update(&gestures, now);
+        let events = self.sequences.update(&gestures, now);
+        self.filter.retain(|id| self.cam1.is_alive(id));
+        self.sequences.retain(|id| self.cam1.is_alive(id));
+        let events_of = |person: usize| {
+            let person = gestures[person].id;
+            events.iter().filter(move |(id, _)| person == Some(*id))
+        };
+        let acts = |person: usize| steady[person].is_some() || events_of(person).next().is_some();
+
+        if (0..gestures.len()).any(acts) {
+            // send frame1 to hpe model
+            models.hpe()?.send(
+                frame,
+                frame1,
+                config.camera1.img_width,
+                config.camera1.img_height,
+            )?;
+
+            // in the meantime calculate positition of head which had a gesture
+            let heads = self.pair(&gestures, &head_positions);
+            let positions = gestures
+                .iter()
+                .zip(heads)
+                .enumerate()
+                .map(|(person, (g, h))| {
+                    let h = match h {
+                        Some(h) if acts(person) => &head_positions[h],
+                        _ => return Ok(None),
+                    };
+
+                    let position = calc_position(
+                        config.triangulation,
+                        &config.camera1,
+                        &g.image_coords(config.camera1.img_width, config.camera1.img_height),
+                        &config.camera2,
+                        &h.image_coords(config.camera2.img_width, config.camera2.img_height),
+                    )?;
+                    Ok(Some(position))
+                })
+                .collect::<Result<Vec<_>, GError>>()?;
+
+            let mut headposes = models
+                .hpe()?
+                .recv_timeout(frame, config.timeouts.get(Process::HPE))?;
+            let poses = match_poses(&gestures, &mut headposes, config.tracker.max_distance);
+
+            // Now get the device in line of sight of each head
+            let devices: Vec<_> = poses
+                .iter()
+                .zip(positions.iter())
+                .map(|(pose, position)| {
+                    let pose = &headposes[(*pose)?];
+                    let line_of_sight = get_los(&config.camera1, position.as_ref()?, &pose.quat());
+                    get_closest_device_in_los_alt(config, line_of_sight)
+                })
+                .collect();
+
+            for (person, (device, g)) in devices.iter().zip(gestures.iter()).enumerate() {
+                let device = match device {
+                    Some(device) => device,
+                    None => continue,
+                };
+                let target = |trigger| Target {
+                    device: device.clone(),
+                    trigger,
+                    value: g.value,
+                    person: g.id,
+                };
+
+                if let Some(gesture) = &steady[person] {
+                    outcome
+                        .targets
+                        .push(target(Trigger::Gesture(gesture.clone())));
+                }
+                outcome.targets.extend(
+                    events_of(person).map(|(_, name)| target(Trigger::Sequence(name.clone()))),
+                );
+            }
+
+            outcome.record.positions = positions
+                .iter()
+                .map(|x| x.as_ref().map(|position| position.to_array()))
+                .collect();
+            outcome.record.devices = devices
+                .iter()
+                .map(|x| x.as_ref().map(|device| device.name.clone()))
+                .collect();
+            outcome.record.headposes = Some(headposes);
+
+            // levels follow the gesture, anything else waits for the device to cool
+            // down, except for a sequence whose own first steps started the cooldown
+            outcome.targets.retain(|target| {
+                let exempt = match target.trigger {
+                    Trigger::Sequence(_) => target.person,
+                    Trigger::Gesture(_) => None,
+                };
+                match target.device.action(&target.trigger) {
+                    Some(Action::Level) => true,
+                    Some(_) => self.filter.cooled_down(&target.device.name, exempt, now),
+                    None => false,
+                }
+            });
+        }
+
+        outcome.record.gestures = gestures;
+        outcome.record.heads = head_positions;
+
+        Ok(outcome)
+    }
+
+    /// Starts the cooldown of the device of a target that was acted on, levels
+    /// don't cool down.
+    pub fn mark_acted(&mut self, target: &Target, now: Instant) {
+        if !matches!(target.device.action(&target.trigger), Some(Action::Level)) {
+            self.filter
+                .mark_acted(&target.device.name, target.person, now);
+        }
+    }
+
+    /// Finds the head camera 2 sees of everyone making a gesture. People stay
+    /// paired for as long as both cameras follow them. New people are paired
+    /// by epipolar distance, or by their order along the axis between the
+    /// cameras when these aren't calibrated.
+    fn pair(
+        &mut self,
+        gestures: &[GesturePrediction],
+        heads: &[HeadPrediction],
+    ) -> Vec<Option<usize>> {
+        let (cam1, cam2) = (&self.cam1, &self.cam2);
+        self.pairs
+            .retain(|gesture, head| cam1.is_alive(*gesture) && cam2.is_alive(*head));
+
+        let mut new_gestures: Vec<_> = gestures
+            .iter()
+            .filter(|g| g.id.is_some_and(|id| !self.pairs.contains_key(&id)))
+            .collect();
+        let mut new_heads: Vec<_> = heads
+            .iter()
+            .filter(|h| {
+                h.id.is_some_and(|id| !self.pairs.values().any(|head| *head == id))
+            })
+            .collect();
+        let new_pairs: Vec<_> = match &self.epipolar {
+            Some(matcher) => {
+                let (camera1, camera2) = (&self.config.camera1, &self.config.camera2);
+                let points1: Vec<_> = new_gestures
+                    .iter()
+                    .map(|g| {
+                        camera1.undistort(&g.image_coords(camera1.img_width, camera1.img_height))
+                    })
+                    .collect();
+                let points2: Vec<_> = new_heads
+                    .iter()
+                    .map(|h| {
+                        camera2.undistort(&h.image_coords(camera2.img_width, camera2.img_height))
+                    })
+                    .collect();
+                matcher
+                    .matches(&points1, &points2)
+                    .into_iter()
+                    .map(|m| (new_gestures[m.cam1], new_heads[m.cam2]))
+                    .collect()
+            }
+            None => {
+                sort_align(&mut new_gestures, self.theta);
+                sort_align(&mut new_heads, self.theta);
+                new_gestures.into_iter().zip(new_heads).collect()
+            }
+        };
+        for (g, h) in new_pairs {
+            if let (Some(gesture), Some(head)) = (g.id, h.id) {
+                self.pairs.insert(gesture, head);
+            }
+        }
+
+        gestures
+            .iter()
+            .map(|g| {
+                let head = self.pairs.get(&g.id?)?;
+                heads.iter().position(|h| h.id == Some(*head))
+            })
+            .collect()
+    }
+}
+
+/// Finds the pose of everyone making a gesture, both come from camera 1 so
+/// the closest ones belong together. Poses get the id of their gesture.
+fn match_poses(
+    gestures: &[GesturePrediction],
+    poses: &mut [HpePrediction],
+    max_distance: f32,
+) -> Vec<Option<usize>> {
+    let cost: Vec<Vec<f32>> = gestures
+        .iter()
+        .map(|g| {
+            poses
+                .iter()
+                .map(|pose| (g.image_x() - pose.image_x()).hypot(g.image_y() - pose.image_y()))
+                .collect()
+        })
+        .collect();
+
+    let matches = tracker::assign(&cost, max_distance);
+    for (g, pose) in gestures.iter().zip(&matches) {
+        if let (Some(id), Some(pose)) = (g.id, pose) {
+            poses[*pose].set_track_id(id);
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixListener, time::Duration};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        mock::{self, CONFIG},
+        models::Gesture,
+        FrameSource,
+    };
+
+    fn hpe(yaw: f32) -> serde_json::Value {
+        json!({"prediction": [{
+            "x1": 30.0, "x2": 34.0, "y1": 22.0, "y2": 26.0,
+            "conf": 1.0, "class": 0.0, "pitch": 0.0, "yaw": yaw, "roll": 0.0,
+        }]})
+    }
+
+    #[test]
+    fn toggle_device_in_line_of_sight() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let socket = mock::socket_path("pipeline");
+        let mut models = Models::new(4, UnixListener::bind(&socket).unwrap());
+
+        // someone straight ahead of camera 1, 100 units out, as seen from camera 2
+        let head_x = 32.0 * (1.0 - 0.2 / 0.5f32.tan());
+        let gesture =
+            |gesture| json!({"prediction": [{"nose_x": 32.0, "nose_y": 24.0, "gesture": gesture}]});
+        let head = json!({"prediction": [{"nose_x": head_x, "nose_y": 24.0}]});
+
+        mock::camera(&socket);
+        mock::model(
+            &socket,
+            "gesture",
+            vec![gesture("Toggle"), gesture("None"), gesture("Toggle")],
+        );
+        mock::model(&socket, "head", vec![head.clone(), head.clone(), head]);
+        // looking back at camera 1, then turned a quarter to the right
+        mock::model(
+            &socket,
+            "hpe",
+            vec![hpe(0.0), hpe(std::f32::consts::FRAC_PI_2)],
+        );
+        models.wait_for_connection(&config);
+
+        let mut cams = models.cams().unwrap();
+        let mut pipeline = Pipeline::new(&config);
+        let mut outcomes = vec![];
+        for _ in 0..3 {
+            let frames = cams.next_frames(Duration::from_secs(1)).unwrap();
+            let outcome = pipeline
+                .process(&models, frames.id, frames.cam1.into(), frames.cam2.into())
+                .unwrap();
+            outcomes.push(outcome);
+        }
+
+        let targets: Vec<Vec<_>> = outcomes
+            .iter()
+            .map(|o| o.targets.iter().map(|t| t.device.name.as_str()).collect())
+            .collect();
+        assert_eq!(targets, vec![vec!["Lamp"], vec![], vec!["Fan"]]);
+
+        let [x, y, z] = outcomes[0].record.positions[0].unwrap();
+        assert!((x - 100.0).abs() < 0.1 && y.abs() < 0.1 && z.abs() < 0.1);
+        assert!(outcomes[1].record.headposes.is_none());
+
+        std::fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn sequence_through_cooldown_of_its_steps() {
+        let config = CONFIG.replace("cooldown = 0", "cooldown = 60000").replace(
+            r#"name = "Lamp""#,
+            r#"name = "Lamp"
+                actions = { toggle = "toggle", double_toggle = "off" }"#,
+        ) + r#"
+            [[sequences]]
+            name = "double_toggle"
+            steps = ["toggle", "toggle"]"#;
+        let config: Config = toml::from_str(&config).unwrap();
+        let socket = mock::socket_path("sequence");
+        let mut models = Models::new(4, UnixListener::bind(&socket).unwrap());
+
+        let head_x = 32.0 * (1.0 - 0.2 / 0.5f32.tan());
+        let gesture =
+            |gesture| json!({"prediction": [{"nose_x": 32.0, "nose_y": 24.0, "gesture": gesture}]});
+        let head = json!({"prediction": [{"nose_x": head_x, "nose_y": 24.0}]});
+
+        mock::camera(&socket);
+        mock::model(
+            &socket,
+            "gesture",
+            vec![gesture("Toggle"), gesture("None"), gesture("Toggle")],
+        );
+        mock::model(&socket, "head", vec![head.clone(), head.clone(), head]);
+        mock::model(&socket, "hpe", vec![hpe(0.0), hpe(0.0)]);
+        models.wait_for_connection(&config);
+
+        let mut cams = models.cams().unwrap();
+        let mut pipeline = Pipeline::new(&config);
+        let mut triggers: Vec<Vec<_>> = vec![];
+        for _ in 0..3 {
+            let frames = cams.next_frames(Duration::from_secs(1)).unwrap();
+            let outcome = pipeline
+                .process(&models, frames.id, frames.cam1.into(), frames.cam2.into())
+                .unwrap();
+            for target in &outcome.targets {
+                pipeline.mark_acted(target, Instant::now());
+            }
+            triggers.push(outcome.targets.into_iter().map(|t| t.trigger).collect());
+        }
+
+        // the second toggle waits for the lamp to cool down, the sequence it ends doesn't
+        assert_eq!(
+            triggers,
+            vec![
+                vec![Trigger::Gesture(Gesture::Toggle)],
+                vec![],
+                vec![Trigger::Sequence("double_toggle".into())],
+            ]
+        );
+
+        std::fs::remove_file(socket).unwrap();
+    }
+}
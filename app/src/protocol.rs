@@ -0,0 +1,325 @@
+use std::fmt;
+use std::io::{Read, Write};
+
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use error_stack::{Result, ResultExt};
+use serde::{Deserialize, Serialize};
+
+use crate::GError;
+
+/// Id of a captured frame pair, carried as the sequence id of every message about it.
+pub type FrameId = u32;
+
+/// "GESE" in ASCII, sent at the start of every frame.
+pub const MAGIC: u32 = 0x4745_5345;
+pub const VERSION: u16 = 1;
+/// magic + version + kind + pixel format + seq + width + height + len
+pub const HEADER_LEN: usize = 24;
+/// Two rgb888 pictures at the full 4056x3040 of the HQ camera, with room to
+/// spare for the json of a prediction. Longer frames are refused unread.
+pub const MAX_FRAME_LEN: u32 = 2 * 4056 * 3040 * 3 + (1 << 20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Hello,
+    Image,
+    Prediction,
+    Capture,
+    Configure,
+    Error,
+}
+
+impl TryFrom<u8> for MessageKind {
+    type Error = GError;
+
+    fn try_from(value: u8) -> std::result::Result<Self, GError> {
+        match value {
+            0 => Ok(Self::Hello),
+            1 => Ok(Self::Image),
+            2 => Ok(Self::Prediction),
+            3 => Ok(Self::Capture),
+            4 => Ok(Self::Configure),
+            5 => Ok(Self::Error),
+            _ => Err(GError::ProtocolError),
+        }
+    }
+}
+
+impl From<MessageKind> for u8 {
+    fn from(value: MessageKind) -> Self {
+        match value {
+            MessageKind::Hello => 0,
+            MessageKind::Image => 1,
+            MessageKind::Prediction => 2,
+            MessageKind::Capture => 3,
+            MessageKind::Configure => 4,
+            MessageKind::Error => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFormat {
+    #[default]
+    None,
+    Rgb888,
+    Bgr888,
+    Jpeg,
+    Png,
+}
+
+impl TryFrom<u8> for PixelFormat {
+    type Error = GError;
+
+    fn try_from(value: u8) -> std::result::Result<Self, GError> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Rgb888),
+            2 => Ok(Self::Bgr888),
+            3 => Ok(Self::Jpeg),
+            4 => Ok(Self::Png),
+            _ => Err(GError::ProtocolError),
+        }
+    }
+}
+
+impl From<PixelFormat> for u8 {
+    fn from(value: PixelFormat) -> Self {
+        match value {
+            PixelFormat::None => 0,
+            PixelFormat::Rgb888 => 1,
+            PixelFormat::Bgr888 => 2,
+            PixelFormat::Jpeg => 3,
+            PixelFormat::Png => 4,
+        }
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Rgb888 => write!(f, "rgb888"),
+            Self::Bgr888 => write!(f, "bgr888"),
+            Self::Jpeg => write!(f, "jpeg"),
+            Self::Png => write!(f, "png"),
+        }
+    }
+}
+
+/// Fixed size header that precedes every message on the socket, in both directions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub kind: MessageKind,
+    pub pixel_format: PixelFormat,
+    pub seq: FrameId,
+    pub width: u32,
+    pub height: u32,
+    pub len: u32,
+}
+
+impl Header {
+    pub fn new(kind: MessageKind, seq: FrameId, len: usize) -> Self {
+        Self {
+            kind,
+            pixel_format: PixelFormat::None,
+            seq,
+            width: 0,
+            height: 0,
+            len: len as u32,
+        }
+    }
+
+    pub fn with_image(mut self, pixel_format: PixelFormat, w: u32, h: u32) -> Self {
+        self.pixel_format = pixel_format;
+        self.width = w;
+        self.height = h;
+        self
+    }
+
+    pub fn expect_kind(&self, kind: MessageKind) -> Result<(), GError> {
+        if self.kind == kind {
+            Ok(())
+        } else {
+            Err(GError::ProtocolError)
+                .attach_printable(format!("Expected {:?} message, got {:?}", kind, self.kind))
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<(), GError> {
+        let mut buf = Vec::with_capacity(HEADER_LEN);
+
+        buf.write_u32::<NetworkEndian>(MAGIC)
+            .and_then(|_| buf.write_u16::<NetworkEndian>(VERSION))
+            .and_then(|_| buf.write_u8(self.kind.into()))
+            .and_then(|_| buf.write_u8(self.pixel_format.into()))
+            .and_then(|_| buf.write_u32::<NetworkEndian>(self.seq))
+            .and_then(|_| buf.write_u32::<NetworkEndian>(self.width))
+            .and_then(|_| buf.write_u32::<NetworkEndian>(self.height))
+            .and_then(|_| buf.write_u32::<NetworkEndian>(self.len))
+            .change_context(GError::IpcError)?;
+
+        w.write_all(&buf).change_context(GError::IpcError)
+    }
+
+    pub fn read_from<R: Read>(mut r: R) -> Result<Self, GError> {
+        let mut buf = [0; HEADER_LEN];
+        r.read_exact(&mut buf).change_context(GError::IpcError)?;
+        let mut buf = &buf[..];
+
+        let magic = buf
+            .read_u32::<NetworkEndian>()
+            .change_context(GError::IpcError)?;
+        if magic != MAGIC {
+            return Err(GError::ProtocolError)
+                .attach_printable(format!("Bad magic number {:#010x}", magic));
+        }
+
+        let version = buf
+            .read_u16::<NetworkEndian>()
+            .change_context(GError::IpcError)?;
+        if version != VERSION {
+            return Err(GError::ProtocolError).attach_printable(format!(
+                "Peer speaks protocol version {}, expected {}",
+                version, VERSION
+            ));
+        }
+
+        let kind = buf.read_u8().change_context(GError::IpcError)?;
+        let kind = MessageKind::try_from(kind)
+            .attach_printable_lazy(|| format!("Unknown message kind {}", kind))?;
+        let pixel_format = buf.read_u8().change_context(GError::IpcError)?;
+        let pixel_format = PixelFormat::try_from(pixel_format)
+            .attach_printable_lazy(|| format!("Unknown pixel format {}", pixel_format))?;
+
+        let mut fields = [0u32; 4];
+        buf.read_u32_into::<NetworkEndian>(&mut fields)
+            .change_context(GError::IpcError)?;
+        let [seq, width, height, len] = fields;
+
+        Ok(Self {
+            kind,
+            pixel_format,
+            seq,
+            width,
+            height,
+            len,
+        })
+    }
+}
+
+/// Payload of the hello frame a process sends right after connecting.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Hello {
+    pub process: String,
+    pub version: String,
+    #[serde(default)]
+    pub pixel_formats: Vec<PixelFormat>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default = "default_max_batch")]
+    pub max_batch: u32,
+}
+
+fn default_max_batch() -> u32 {
+    1
+}
+
+impl Hello {
+    pub fn from_slice(msg: &[u8]) -> Result<Self, GError> {
+        serde_json::from_slice(msg)
+            .change_context(GError::ProtocolError)
+            .attach_printable("Malformed hello message")
+    }
+
+    pub fn supports(&self, pixel_format: PixelFormat) -> bool {
+        self.pixel_formats.contains(&pixel_format)
+    }
+}
+
+pub fn write_frame<W: Write>(mut w: W, header: &Header, msg: &[u8]) -> Result<(), GError> {
+    header.write_to(&mut w)?;
+    w.write_all(msg).change_context(GError::IpcError)
+}
+
+pub fn read_frame<R: Read>(mut r: R) -> Result<(Header, Vec<u8>), GError> {
+    let header = Header::read_from(&mut r)?;
+    if header.len > MAX_FRAME_LEN {
+        return Err(GError::ProtocolError).attach_printable(format!(
+            "Frame of {} bytes is longer than the {} allowed",
+            header.len, MAX_FRAME_LEN
+        ));
+    }
+
+    let mut msg = vec![0; header.len as usize];
+    r.read_exact(&mut msg).change_context(GError::IpcError)?;
+
+    Ok((header, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_roundtrip() {
+        let header = Header::new(MessageKind::Image, 42, 1296 * 972 * 3).with_image(
+            PixelFormat::Rgb888,
+            1296,
+            972,
+        );
+
+        let mut buf = vec![];
+        write_frame(&mut buf, &header, &[1, 2, 3]).unwrap();
+        assert_eq!(buf.len(), HEADER_LEN + 3);
+
+        let read = Header::read_from(&buf[..]).unwrap();
+        assert_eq!(header, read);
+    }
+
+    #[test]
+    fn parse_hello() {
+        let hello = Hello::from_slice(
+            br#"{"process": "gesture", "version": "0.2.0", "pixel_formats": ["rgb888", "jpeg"]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(hello.process, "gesture");
+        assert!(hello.supports(PixelFormat::Rgb888));
+        assert!(!hello.supports(PixelFormat::Png));
+        assert_eq!(hello.max_batch, 1);
+        assert!(Hello::from_slice(b"gesture").is_err());
+    }
+
+    #[test]
+    fn reject_bad_magic() {
+        let mut buf = vec![];
+        write_frame(&mut buf, &Header::new(MessageKind::Hello, 0, 0), &[]).unwrap();
+        buf[0] = b'X';
+
+        let err = Header::read_from(&buf[..]).unwrap_err();
+        assert!(matches!(err.current_context(), GError::ProtocolError));
+    }
+
+    #[test]
+    fn reject_version_mismatch() {
+        let mut buf = vec![];
+        write_frame(&mut buf, &Header::new(MessageKind::Hello, 0, 0), &[]).unwrap();
+        buf[5] = VERSION as u8 + 1;
+
+        assert!(Header::read_from(&buf[..]).is_err());
+    }
+
+    #[test]
+    fn reject_oversized_frame() {
+        let mut buf = vec![];
+        Header::new(MessageKind::Image, 0, 0)
+            .write_to(&mut buf)
+            .unwrap();
+        buf[HEADER_LEN - 4..].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let err = read_frame(&buf[..]).unwrap_err();
+        assert!(matches!(err.current_context(), GError::ProtocolError));
+    }
+}
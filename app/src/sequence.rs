@@ -0,0 +1,195 @@
+//! Recognizes sequences of gestures done over several frames.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::SequenceConfig,
+    models::{Gesture, GesturePrediction},
+    tracker::TrackId,
+    HasTrackId,
+};
+
+pub struct SequenceRecognizer {
+    sequences: Vec<SequenceConfig>,
+    /// Progress of every person through every sequence.
+    people: HashMap<TrackId, Vec<Progress>>,
+}
+
+#[derive(Default, Debug, Clone)]
+struct Progress {
+    /// Steps done so far.
+    done: usize,
+    /// When the last step done was let go of.
+    done_at: Option<Instant>,
+    /// Since when the gesture of the next step is held.
+    held_since: Option<Instant>,
+    /// Gesture of the last step done, it has to be let go of before it counts again.
+    blocked: Option<Gesture>,
+}
+
+impl SequenceRecognizer {
+    pub fn new(sequences: &[SequenceConfig]) -> Self {
+        Self {
+            sequences: sequences
+                .iter()
+                .filter(|sequence| !sequence.steps.is_empty())
+                .cloned()
+                .collect(),
+            people: HashMap::new(),
+        }
+    }
+
+    /// Takes the tracked gestures of a frame. Returns the person and the name
+    /// of every sequence finished.
+    pub fn update(
+        &mut self,
+        gestures: &[GesturePrediction],
+        now: Instant,
+    ) -> Vec<(TrackId, String)> {
+        let mut events = vec![];
+        for prediction in gestures {
+            let id = match prediction.track_id() {
+                Some(id) => id,
+                None => continue,
+            };
+            let progress = self
+                .people
+                .entry(id)
+                .or_insert_with(|| vec![Progress::default(); self.sequences.len()]);
+
+            for (sequence, progress) in self.sequences.iter().zip(progress) {
+                if progress.advance(sequence, &prediction.gesture, now) {
+                    events.push((id, sequence.name.clone()));
+                }
+            }
+        }
+        events
+    }
+
+    /// Forgets the people the tracker lost.
+    pub fn retain(&mut self, alive: impl Fn(TrackId) -> bool) {
+        self.people.retain(|id, _| alive(*id));
+    }
+}
+
+impl Progress {
+    /// Returns whether the gesture finished the sequence.
+    fn advance(&mut self, sequence: &SequenceConfig, gesture: &Gesture, now: Instant) -> bool {
+        if self.blocked.as_ref() == Some(gesture) {
+            self.done_at = Some(now);
+            return false;
+        }
+        self.blocked = None;
+
+        let too_late = self
+            .done_at
+            .is_some_and(|done_at| now - done_at > Duration::from_millis(sequence.gap));
+        if self.done > 0 && too_late {
+            self.restart();
+        }
+
+        let step = &sequence.steps[self.done];
+        if *gesture != step.gesture {
+            self.held_since = None;
+            // any other gesture breaks the sequence, it may start a new one
+            if !gesture.is_none() && self.done > 0 {
+                self.restart();
+                return self.advance(sequence, gesture, now);
+            }
+            return false;
+        }
+
+        let held_since = *self.held_since.get_or_insert(now);
+        if now - held_since < Duration::from_millis(step.hold) {
+            return false;
+        }
+
+        self.done += 1;
+        self.done_at = Some(now);
+        self.held_since = None;
+        self.blocked = Some(gesture.clone());
+        if self.done < sequence.steps.len() {
+            return false;
+        }
+
+        self.restart();
+        true
+    }
+
+    fn restart(&mut self) {
+        self.done = 0;
+        self.done_at = None;
+        self.held_since = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognize_sequences() {
+        use Gesture::*;
+
+        #[derive(serde::Deserialize)]
+        struct Sequences {
+            sequences: Vec<SequenceConfig>,
+        }
+        let Sequences { sequences } = toml::from_str(
+            r#"
+            [[sequences]]
+            name = "double_toggle"
+            steps = ["toggle", "toggle"]
+
+            [[sequences]]
+            name = "long_point"
+            steps = [{ gesture = "point", hold = 1000 }]
+
+            [[sequences]]
+            name = "swipe_point"
+            steps = ["swipe_right", "point"]
+            gap = 500"#,
+        )
+        .unwrap();
+        let mut recognizer = SequenceRecognizer::new(&sequences);
+
+        let start = Instant::now();
+        let mut feed = |ms: u64, gestures: &[Gesture]| {
+            let gestures: Vec<_> = gestures
+                .iter()
+                .zip(1..)
+                .map(|(gesture, id)| GesturePrediction {
+                    id: Some(id),
+                    gesture: gesture.clone(),
+                    ..Default::default()
+                })
+                .collect();
+            recognizer.update(&gestures, start + Duration::from_millis(ms))
+        };
+        let event = |person, name: &str| vec![(person, name.to_owned())];
+
+        // toggling twice, the second person only toggles once
+        assert!(feed(0, &[Toggle, Toggle]).is_empty());
+        assert!(feed(200, &[Toggle, None]).is_empty());
+        assert!(feed(400, &[None, None]).is_empty());
+        assert_eq!(feed(600, &[Toggle, None]), event(1, "double_toggle"));
+        // holding the toggle doesn't start another one
+        assert!(feed(800, &[Toggle, None]).is_empty());
+        assert!(feed(1000, &[None, None]).is_empty());
+
+        // pointing for a second, both on its own and after a swipe
+        assert!(feed(1200, &[SwipeRight, Point]).is_empty());
+        assert_eq!(feed(1400, &[Point, Point]), event(1, "swipe_point"));
+        assert!(feed(2000, &[Point, Point]).is_empty());
+        assert_eq!(feed(2200, &[Point, Point]), event(2, "long_point"));
+        assert_eq!(feed(2400, &[Point, None]), event(1, "long_point"));
+
+        // too slow
+        assert!(feed(3000, &[SwipeRight, None]).is_empty());
+        assert!(feed(3200, &[None, None]).is_empty());
+        assert!(feed(3800, &[Point, None]).is_empty());
+    }
+}
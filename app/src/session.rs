@@ -0,0 +1,309 @@
+//! Session files capture what the orchestrator saw and decided, so an interaction
+//! can be analysed and replayed offline.
+//!
+//! A session is a tar archive. Every recorded frame adds a png of each camera
+//! under `cam1/` and `cam2/`, which the replay source plays back as is, and a
+//! [`Record`] under `frames/`, all named by the zero padded frame id. The tar
+//! headers double as the index, so a session cut short by a crash can still
+//! be read up to the last complete frame.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use error_stack::{Report, Result, ResultExt};
+use flume::{unbounded, Sender};
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::RecordConfig,
+    models::{GesturePreds, HPEPreds, HeadPreds},
+    protocol::FrameId,
+    GError,
+};
+
+/// Everything computed for one frame pair.
+#[derive(Default, Debug, Deserialize, Serialize)]
+pub struct Record {
+    pub frame: FrameId,
+    /// Milliseconds since recording started.
+    pub time: u64,
+    pub gestures: GesturePreds,
+    pub heads: HeadPreds,
+    /// Only estimated when someone made a gesture.
+    pub headposes: Option<HPEPreds>,
+    /// Position of each person that made a gesture.
+    pub positions: Vec<Option<[f32; 3]>>,
+    /// Device chosen for each person that made a gesture.
+    pub devices: Vec<Option<String>>,
+    /// Why processing the frame failed, the predictions are empty then.
+    pub error: Option<String>,
+}
+
+type Recorded = (Arc<[u8]>, Arc<[u8]>, Record);
+
+/// Writes a session in the background, encoding the images off the main loop.
+/// Everything queued is written out and the session closed when dropped.
+pub struct Recorder {
+    sender: Option<Sender<Recorded>>,
+    handle: Option<JoinHandle<()>>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(
+        config: &RecordConfig,
+        w1: u32,
+        h1: u32,
+        w2: u32,
+        h2: u32,
+    ) -> Result<Self, GError> {
+        // never overwrite an earlier session
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&config.path)
+            .change_context(GError::ConfigError)
+            .attach_printable_lazy(|| format!("Couldn't create {}", config.path.display()))?;
+        let mut builder = tar::Builder::new(file);
+        let (sender, receiver) = unbounded::<Recorded>();
+
+        let handle = thread::spawn(move || {
+            for (img1, img2, record) in receiver.iter() {
+                let res = append_png(&mut builder, "cam1", record.frame, &img1, w1, h1)
+                    .and_then(|_| append_png(&mut builder, "cam2", record.frame, &img2, w2, h2))
+                    .and_then(|_| append_record(&mut builder, &record));
+
+                if let Err(e) = res {
+                    println!("Couldn't record frame {}: {:?}", record.frame, e);
+                }
+            }
+
+            if let Err(e) = builder.finish() {
+                println!("Couldn't finish the session file: {:?}", e);
+            }
+        });
+        println!("Recording session to {}", config.path.display());
+
+        Ok(Self {
+            sender: Some(sender),
+            handle: Some(handle),
+            start: Instant::now(),
+        })
+    }
+
+    /// Queues a frame pair and what was computed for it, filling in the time.
+    pub fn record(
+        &self,
+        img1: Arc<[u8]>,
+        img2: Arc<[u8]>,
+        mut record: Record,
+    ) -> Result<(), GError> {
+        record.time = self.start.elapsed().as_millis() as u64;
+        self.sender
+            .as_ref()
+            .expect("only taken on drop")
+            .send((img1, img2, record))
+            .change_context(GError::CommError)
+    }
+
+    /// Records a frame pair that couldn't be processed, along with the reason.
+    pub fn record_error(
+        &self,
+        img1: Arc<[u8]>,
+        img2: Arc<[u8]>,
+        frame: FrameId,
+        err: &Report<GError>,
+    ) -> Result<(), GError> {
+        let record = Record {
+            frame,
+            error: Some(format!("{:?}", err)),
+            ..Default::default()
+        };
+        self.record(img1, img2, record)
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Padded to the widest frame id, so entries sort in frame order.
+fn entry_name(dir: &str, frame: FrameId, ext: &str) -> String {
+    format!("{}/{:010}.{}", dir, frame, ext)
+}
+
+fn append<W: Write>(builder: &mut tar::Builder<W>, name: &str, data: &[u8]) -> Result<(), GError> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    builder
+        .append_data(&mut header, name, data)
+        .and_then(|_| builder.get_mut().flush())
+        .change_context(GError::SessionError)
+        .attach_printable_lazy(|| format!("Couldn't write {}", name))
+}
+
+fn append_png<W: Write>(
+    builder: &mut tar::Builder<W>,
+    cam: &str,
+    frame: FrameId,
+    img: &[u8],
+    w: u32,
+    h: u32,
+) -> Result<(), GError> {
+    let mut png = vec![];
+    PngEncoder::new(&mut png)
+        .write_image(img, w, h, ColorType::Rgb8)
+        .change_context(GError::SessionError)
+        .attach_printable_lazy(|| format!("Couldn't encode frame {} of {}", frame, cam))?;
+
+    append(builder, &entry_name(cam, frame, "png"), &png)
+}
+
+fn append_record<W: Write>(builder: &mut tar::Builder<W>, record: &Record) -> Result<(), GError> {
+    let json = serde_json::to_vec(record).change_context(GError::SessionError)?;
+
+    append(builder, &entry_name("frames", record.frame, "json"), &json)
+}
+
+/// A recorded session opened for analysis, images are played back with the replay source.
+pub struct Session {
+    file: File,
+    /// Offset and length of the record of each frame.
+    index: BTreeMap<FrameId, (u64, u64)>,
+}
+
+impl Session {
+    pub fn open(path: &Path) -> Result<Self, GError> {
+        let file = File::open(path)
+            .change_context(GError::ConfigError)
+            .attach_printable_lazy(|| format!("Couldn't open {}", path.display()))?;
+
+        let mut index = BTreeMap::new();
+        let mut tar = tar::Archive::new(&file);
+        for entry in tar
+            .entries_with_seek()
+            .change_context(GError::SessionError)?
+        {
+            let entry = entry.change_context(GError::SessionError)?;
+            let path = entry.path().change_context(GError::SessionError)?;
+
+            if !path.starts_with("frames") {
+                continue;
+            }
+            let frame = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok());
+            if let Some(frame) = frame {
+                index.insert(frame, (entry.raw_file_position(), entry.size()));
+            }
+        }
+
+        Ok(Self { file, index })
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = FrameId> + '_ {
+        self.index.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn record(&self, frame: FrameId) -> Result<Record, GError> {
+        let (offset, len) = *self
+            .index
+            .get(&frame)
+            .ok_or(GError::ConfigError)
+            .attach_printable_lazy(|| format!("Frame {} is not in the session", frame))?;
+
+        let mut file = &self.file;
+        let mut json = vec![0; len as usize];
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut json))
+            .change_context(GError::SessionError)?;
+
+        serde_json::from_slice(&json)
+            .change_context(GError::SessionError)
+            .attach_printable_lazy(|| format!("Malformed record of frame {}", frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{camera::ReplaySource, config::ReplayConfig, FrameSource};
+
+    #[test]
+    fn record_and_replay() {
+        let path =
+            std::env::temp_dir().join(format!("gesture-ease-session-{}.tar", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let recorder = Recorder::create(&RecordConfig { path: path.clone() }, 2, 1, 1, 2).unwrap();
+        for frame in [3, 4] {
+            let record = Record {
+                frame,
+                positions: vec![Some([1.0, 2.0, 3.0]), None],
+                devices: vec![Some("Bulb_1".into()), None],
+                ..Default::default()
+            };
+            recorder
+                .record(vec![frame as u8; 6].into(), vec![7; 6].into(), record)
+                .unwrap();
+        }
+        // past what six digits of padding sort right
+        let err = Report::new(GError::Timeout);
+        recorder
+            .record_error(vec![9; 6].into(), vec![7; 6].into(), 1_000_000, &err)
+            .unwrap();
+        drop(recorder);
+
+        let session = Session::open(&path).unwrap();
+        assert_eq!(session.frames().collect::<Vec<_>>(), vec![3, 4, 1_000_000]);
+        let record = session.record(4).unwrap();
+        assert_eq!(record.devices[0].as_deref(), Some("Bulb_1"));
+        assert!(record.headposes.is_none());
+        assert!(record.error.is_none());
+        let failed = session.record(1_000_000).unwrap();
+        assert!(failed.error.unwrap().contains("Timed out"));
+        assert!(session.record(5).is_err());
+
+        let replay = ReplayConfig {
+            path: path.clone(),
+            fps: 1000.0,
+            repeat: false,
+        };
+        let mut source = ReplaySource::open(&replay, 2, 1, 1, 2).unwrap();
+        let frames = source.next_frames(Duration::ZERO).unwrap();
+        assert_eq!(frames.cam1, vec![3; 6]);
+        assert_eq!(frames.cam2, vec![7; 6]);
+        source.next_frames(Duration::ZERO).unwrap();
+        let frames = source.next_frames(Duration::ZERO).unwrap();
+        assert_eq!(frames.cam1, vec![9; 6]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
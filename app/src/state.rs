@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use error_stack::{Result, ResultExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actuators::Actuators,
+    config::{Action, Device},
+    GError,
+};
+
+/// Known state of a device, the level once one was set.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Known {
+    on: bool,
+    level: Option<f32>,
+}
+
+/// A device as saved, just whether it's on unless a level was set.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Saved {
+    Switch(bool),
+    Level { on: bool, level: f32 },
+}
+
+impl From<Known> for Saved {
+    fn from(known: Known) -> Self {
+        match known.level {
+            Some(level) => Self::Level {
+                on: known.on,
+                level,
+            },
+            None => Self::Switch(known.on),
+        }
+    }
+}
+
+impl From<&Saved> for Known {
+    fn from(saved: &Saved) -> Self {
+        match *saved {
+            Saved::Switch(on) => Self { on, level: None },
+            Saved::Level { on, level } => Self {
+                on,
+                level: Some(level),
+            },
+        }
+    }
+}
+
+/// Last known on/off state and level of every device, keyed by device name.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceState {
+    states: Arc<Mutex<HashMap<String, Known>>>,
+    actuators: Actuators,
+    file: Option<PathBuf>,
+}
+
+impl DeviceState {
+    /// Opens the actuator of every device.
+    pub fn new(devices: &[Device]) -> Self {
+        let states = devices
+            .iter()
+            .map(|device| {
+                let known = Known {
+                    on: device.initially_on,
+                    level: None,
+                };
+                (device.name.clone(), known)
+            })
+            .collect();
+
+        Self {
+            states: Arc::new(Mutex::new(states)),
+            actuators: Actuators::open(devices),
+            file: None,
+        }
+    }
+
+    /// Restores the states saved to `file` and keeps saving every change to it.
+    /// Devices missing from the file start in their configured state.
+    pub fn open(devices: &[Device], file: &Path) -> Self {
+        let mut state = Self::new(devices);
+        state.file = Some(file.to_owned());
+
+        let saved = match fs::read(file) {
+            Ok(saved) => saved,
+            Err(_) => return state,
+        };
+        match serde_json::from_slice::<HashMap<String, Saved>>(&saved) {
+            Ok(saved) => {
+                let mut states = state.states.lock().unwrap();
+                for (name, known) in states.iter_mut() {
+                    if let Some(saved) = saved.get(name) {
+                        *known = saved.into();
+                    }
+                }
+            }
+            Err(e) => println!("Ignoring unreadable {}: {}", file.display(), e),
+        }
+
+        state
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        self.states.lock().unwrap().get(name).map(|known| known.on)
+    }
+
+    /// Level last set on the device, if any.
+    pub fn level(&self, name: &str) -> Option<f32> {
+        self.states.lock().unwrap().get(name)?.level
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, bool> {
+        self.states
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, known)| (name.clone(), known.on))
+            .collect()
+    }
+
+    /// Drives every device to its known state, done once at startup. A device
+    /// that can't be reached is left alone and keeps its known state.
+    pub fn apply(&self, devices: &[Device]) {
+        for device in devices {
+            let known = self
+                .states
+                .lock()
+                .unwrap()
+                .get(&device.name)
+                .copied()
+                .unwrap_or_default();
+            let res = match known.level {
+                Some(level) if known.on => self.set_level(device, level),
+                _ => self.set(device, known.on),
+            };
+            if let Err(e) = res {
+                println!("Couldn't restore {}: {:?}", device.name, e);
+            }
+        }
+    }
+
+    pub fn set(&self, device: &Device, on: bool) -> Result<(), GError> {
+        self.actuators.get(&device.name)?.set(on)?;
+
+        if self.update(device, |known| known.on = on) {
+            println!("{} is now {}", device.name, if on { "on" } else { "off" });
+        }
+
+        Ok(())
+    }
+
+    /// Changes the known state of the device, saving it if it changed.
+    /// Returns whether it did.
+    fn update(&self, device: &Device, change: impl FnOnce(&mut Known)) -> bool {
+        let changed = {
+            let mut states = self.states.lock().unwrap();
+            let known = states.entry(device.name.clone()).or_default();
+            let prev = *known;
+            change(known);
+            prev != *known
+        };
+
+        if changed {
+            if let Err(e) = self.save() {
+                println!("Couldn't save device state: {:?}", e);
+            }
+        }
+        changed
+    }
+
+    /// Replaces the file in one go, so a crash never leaves half of it behind.
+    fn save(&self) -> Result<(), GError> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let saved: HashMap<String, Saved> = self
+            .states
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, known)| (name.clone(), (*known).into()))
+            .collect();
+        let json = serde_json::to_vec_pretty(&saved).change_context(GError::ConfigError)?;
+        let mut tmp = file.clone().into_os_string();
+        tmp.push(".tmp");
+
+        fs::File::create(&tmp)
+            .and_then(|mut f| {
+                std::io::Write::write_all(&mut f, &json)?;
+                f.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp, file))
+            .change_context(GError::ConfigError)
+            .attach_printable_lazy(|| format!("Couldn't write {}", file.display()))
+    }
+
+    /// Flips the device from what it reports, or else its last known state.
+    /// Returns the new state.
+    pub fn toggle(&self, device: &Device) -> Result<bool, GError> {
+        let reported = self.actuators.get(&device.name)?.reported_state();
+        let on = !reported
+            .or_else(|| self.get(&device.name))
+            .unwrap_or_default();
+
+        self.set(device, on)?;
+        Ok(on)
+    }
+
+    /// Sets a level between 0 and 1, the device counts as on above 0.
+    pub fn set_level(&self, device: &Device, level: f32) -> Result<(), GError> {
+        let level = level.clamp(0.0, 1.0);
+        self.actuators.get(&device.name)?.set_level(level)?;
+
+        self.update(device, |known| {
+            known.on = level > 0.0;
+            known.level = Some(level);
+        });
+
+        Ok(())
+    }
+
+    /// Switching actions go through the known state, the rest straight to the
+    /// device. `value` is the reading of the gesture, used for levels.
+    pub fn perform(
+        &self,
+        device: &Device,
+        action: Action,
+        value: Option<f32>,
+    ) -> Result<(), GError> {
+        match action {
+            Action::Toggle => self.toggle(device).map(|_| ()),
+            Action::On => self.set(device, true),
+            Action::Off => self.set(device, false),
+            Action::Level => {
+                let level = value
+                    .ok_or(GError::ActuatorError)
+                    .attach_printable("The gesture carries no value to set a level from")?;
+                self.set_level(device, level)
+            }
+            _ => self.actuators.get(&device.name)?.perform(action),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_simulated_devices() {
+        #[derive(serde::Deserialize)]
+        struct Devices {
+            devices: Vec<Device>,
+        }
+        let Devices { devices } = toml::from_str(
+            r#"
+            [[devices]]
+            name = "Lamp"
+            min_x = 0
+            min_y = 0
+            min_z = 0
+            max_x = 1
+            max_y = 1
+            max_z = 1
+            actuator = { kind = "simulated" }
+
+            [[devices]]
+            name = "Fan"
+            initially_on = true
+            min_x = 2
+            min_y = 2
+            min_z = 2
+            max_x = 3
+            max_y = 3
+            max_z = 3
+            actuator = { kind = "simulated" }"#,
+        )
+        .unwrap();
+        let states = DeviceState::new(&devices);
+        states.apply(&devices);
+
+        let (lamp, fan) = (&devices[0], &devices[1]);
+        let reported = |name: &str| states.actuators.get(name).unwrap().reported_state();
+        assert_eq!(reported("Fan"), Some(true));
+        assert!(states.toggle(lamp).unwrap());
+        states.set(lamp, true).unwrap();
+        assert_eq!(states.get("Lamp"), Some(true));
+        assert!(!states.toggle(fan).unwrap());
+        assert_eq!(
+            states.snapshot(),
+            HashMap::from([("Lamp".into(), true), ("Fan".into(), false)])
+        );
+
+        // the pipeline acts on clones, which drive the actuators opened for apply
+        let (cloned, fan) = (states.clone(), fan.clone());
+        cloned.perform(&fan, Action::Toggle, None).unwrap();
+        cloned.perform(&fan, Action::On, None).unwrap();
+        assert_eq!(reported("Fan"), Some(true));
+        assert_eq!(states.get("Fan"), Some(true));
+    }
+
+    #[test]
+    fn restore_saved_state() {
+        let devices: Vec<Device> = ["Lamp", "Fan"]
+            .into_iter()
+            .map(|name| {
+                toml::from_str(&format!(
+                    r#"
+                    name = "{}"
+                    min_x = 0
+                    min_y = 0
+                    min_z = 0
+                    max_x = 1
+                    max_y = 1
+                    max_z = 1
+                    actuator = {{ kind = "simulated" }}"#,
+                    name
+                ))
+                .unwrap()
+            })
+            .collect();
+        let file = std::env::temp_dir().join(format!("device_state_{}.json", std::process::id()));
+        fs::write(&file, r#"{"Fan": true, "Heater": true}"#).unwrap();
+
+        let states = DeviceState::open(&devices, &file);
+        assert_eq!(states.get("Fan"), Some(true));
+        assert_eq!(states.get("Lamp"), Some(false));
+        assert_eq!(states.get("Heater"), None);
+
+        states.set(&devices[0], true).unwrap();
+        states.set_level(&devices[1], 0.4).unwrap();
+        let restored = DeviceState::open(&devices, &file);
+        assert_eq!(restored.snapshot(), states.snapshot());
+        assert_eq!(restored.level("Fan"), Some(0.4));
+        assert_eq!(restored.level("Lamp"), None);
+
+        fs::remove_file(file).unwrap();
+    }
+}
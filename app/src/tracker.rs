@@ -0,0 +1,334 @@
+//! Follows people from frame to frame, giving each one an id that stays the
+//! same for as long as they're seen.
+
+use std::time::Instant;
+
+use nalgebra::{Matrix2, Matrix2x4, Matrix4, Vector2, Vector4};
+
+use crate::{config::TrackerConfig, HasImagePosition, HasTrackId};
+
+pub type TrackId = u32;
+
+/// Cost standing in for pairs too far apart, high enough that the assignment
+/// only uses them when nothing else is left.
+const GATED: f32 = 1e6;
+
+/// Tracks the detections of one camera.
+pub struct Tracker {
+    config: TrackerConfig,
+    tracks: Vec<Track>,
+    next_id: TrackId,
+    last: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Track {
+    id: TrackId,
+    kalman: Kalman,
+    /// Frames in a row without a detection.
+    missed: u32,
+}
+
+impl Tracker {
+    pub fn new(config: &TrackerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            tracks: vec![],
+            next_id: 1,
+            last: None,
+        }
+    }
+
+    /// Gives every detection the id of the person it's closest to where they
+    /// were expected, or a new id.
+    pub fn update<T: HasImagePosition + HasTrackId>(&mut self, detections: &mut [T], now: Instant) {
+        let dt = self
+            .last
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f32());
+        self.last = Some(now);
+
+        for track in &mut self.tracks {
+            track.kalman.predict(dt, self.config.process_noise);
+        }
+
+        let cost: Vec<Vec<f32>> = self
+            .tracks
+            .iter()
+            .map(|track| {
+                detections
+                    .iter()
+                    .map(|d| track.kalman.distance(d.image_x(), d.image_y()))
+                    .collect()
+            })
+            .collect();
+
+        let mut matched = vec![false; detections.len()];
+        for (track, detection) in self
+            .tracks
+            .iter_mut()
+            .zip(assign(&cost, self.config.max_distance))
+        {
+            match detection {
+                Some(i) => {
+                    let detection = &mut detections[i];
+                    track.kalman.correct(
+                        detection.image_x(),
+                        detection.image_y(),
+                        self.config.measurement_noise,
+                    );
+                    track.missed = 0;
+                    detection.set_track_id(track.id);
+                    matched[i] = true;
+                }
+                None => track.missed += 1,
+            }
+        }
+
+        let max_missed = self.config.max_missed;
+        self.tracks.retain(|track| track.missed <= max_missed);
+
+        for (detection, _) in detections.iter_mut().zip(matched).filter(|(_, m)| !m) {
+            let id = self.next_id;
+            self.next_id += 1;
+            detection.set_track_id(id);
+            self.tracks.push(Track {
+                id,
+                kalman: Kalman::new(detection.image_x(), detection.image_y(), &self.config),
+                missed: 0,
+            });
+        }
+    }
+
+    /// Whether the person is still followed, even if they weren't seen in the last frame.
+    pub fn is_alive(&self, id: TrackId) -> bool {
+        self.tracks.iter().any(|track| track.id == id)
+    }
+}
+
+/// Pairs rows with columns at the lowest total cost, leaving out pairs
+/// costing more than `max_cost`. Returns the column of every row.
+pub fn assign(cost: &[Vec<f32>], max_cost: f32) -> Vec<Option<usize>> {
+    let gated: Vec<Vec<f32>> = cost
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|c| if *c <= max_cost { *c } else { GATED })
+                .collect()
+        })
+        .collect();
+
+    hungarian(&gated)
+        .into_iter()
+        .enumerate()
+        .map(|(row, col)| col.filter(|col| cost[row][*col] <= max_cost))
+        .collect()
+}
+
+/// Hungarian method on a rectangular matrix of finite costs. Returns the
+/// column of every row, some rows are left without one when there are more
+/// rows than columns.
+#[allow(clippy::needless_range_loop)]
+pub fn hungarian(cost: &[Vec<f32>]) -> Vec<Option<usize>> {
+    let rows = cost.len();
+    let cols = cost.first().map_or(0, |row| row.len());
+    if rows == 0 || cols == 0 {
+        return vec![None; rows];
+    }
+    if rows > cols {
+        let transposed: Vec<Vec<f32>> = (0..cols)
+            .map(|col| cost.iter().map(|row| row[col]).collect())
+            .collect();
+        let mut assignment = vec![None; rows];
+        for (col, row) in hungarian(&transposed).into_iter().enumerate() {
+            if let Some(row) = row {
+                assignment[row] = Some(col);
+            }
+        }
+        return assignment;
+    }
+
+    // rows and columns count from 1, column 0 stands for the row being added
+    let mut u = vec![0f64; rows + 1];
+    let mut v = vec![0f64; cols + 1];
+    // row matched to each column
+    let mut p = vec![0usize; cols + 1];
+    let mut way = vec![0usize; cols + 1];
+
+    for row in 1..=rows {
+        p[0] = row;
+        let mut j0 = 0;
+        let mut minv = vec![f64::INFINITY; cols + 1];
+        let mut used = vec![false; cols + 1];
+
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=cols {
+                if used[j] {
+                    continue;
+                }
+                let cur = cost[i0 - 1][j - 1] as f64 - u[i0] - v[j];
+                if cur < minv[j] {
+                    minv[j] = cur;
+                    way[j] = j0;
+                }
+                if minv[j] < delta {
+                    delta = minv[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=cols {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if p[j0] == 0 {
+                break;
+            }
+        }
+
+        while j0 != 0 {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+    for col in 1..=cols {
+        if p[col] != 0 {
+            assignment[p[col] - 1] = Some(col - 1);
+        }
+    }
+    assignment
+}
+
+/// Constant velocity kalman filter over a position in the image.
+#[derive(Debug, Clone)]
+struct Kalman {
+    /// `[x, y, vx, vy]`
+    x: Vector4<f32>,
+    p: Matrix4<f32>,
+}
+
+impl Kalman {
+    fn new(x: f32, y: f32, config: &TrackerConfig) -> Self {
+        let r = config.measurement_noise.powi(2);
+        // the speed isn't known yet, anything up to max_distance every 100ms is likely
+        let v = (config.max_distance * 10.0).powi(2);
+        Self {
+            x: Vector4::new(x, y, 0.0, 0.0),
+            p: Matrix4::from_diagonal(&Vector4::new(r, r, v, v)),
+        }
+    }
+
+    fn predict(&mut self, dt: f32, accel: f32) {
+        #[rustfmt::skip]
+        let f = Matrix4::new(
+            1.0, 0.0, dt, 0.0,
+            0.0, 1.0, 0.0, dt,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        let (a, b, c) = (dt.powi(4) / 4.0, dt.powi(3) / 2.0, dt.powi(2));
+        #[rustfmt::skip]
+        let q = Matrix4::new(
+            a, 0.0, b, 0.0,
+            0.0, a, 0.0, b,
+            b, 0.0, c, 0.0,
+            0.0, b, 0.0, c,
+        ) * accel.powi(2);
+
+        self.x = f * self.x;
+        self.p = f * self.p * f.transpose() + q;
+    }
+
+    fn correct(&mut self, x: f32, y: f32, noise: f32) {
+        let h = Matrix2x4::new(1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0);
+        let r = Matrix2::identity() * noise.powi(2);
+
+        let residual = Vector2::new(x, y) - h * self.x;
+        let s = h * self.p * h.transpose() + r;
+        let s_inv = match s.try_inverse() {
+            Some(s_inv) => s_inv,
+            None => return,
+        };
+        let k = self.p * h.transpose() * s_inv;
+
+        self.x += k * residual;
+        self.p = (Matrix4::identity() - k * h) * self.p;
+    }
+
+    fn distance(&self, x: f32, y: f32) -> f32 {
+        (self.x[0] - x).hypot(self.x[1] - y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::models::HeadPrediction;
+
+    #[test]
+    fn assign_lowest_cost() {
+        let cost = vec![
+            vec![4.0, 1.0, 3.0],
+            vec![2.0, 0.0, 5.0],
+            vec![3.0, 2.0, 2.0],
+        ];
+        assert_eq!(hungarian(&cost), [Some(1), Some(0), Some(2)]);
+
+        let cost = vec![vec![1.0, 50.0], vec![2.0, 60.0], vec![70.0, 80.0]];
+        assert_eq!(assign(&cost, 10.0), [Some(0), None, None]);
+    }
+
+    #[test]
+    fn keep_ids_when_paths_cross() {
+        let mut tracker = Tracker::new(&TrackerConfig {
+            max_distance: 30.0,
+            ..Default::default()
+        });
+        let start = Instant::now();
+
+        // two people walking towards each other along the same line, one drops out for a frame
+        let mut ids = vec![];
+        for step in 0..10 {
+            let mut heads: Vec<_> = [100.0 + 20.0 * step as f32, 310.0 - 20.0 * step as f32]
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| !(step == 4 && *i == 1))
+                .map(|(_, x)| HeadPrediction {
+                    nose_x: x,
+                    nose_y: 100.0,
+                    ..Default::default()
+                })
+                .collect();
+            // detections come in no particular order
+            heads.reverse();
+
+            tracker.update(&mut heads, start + Duration::from_millis(100 * step));
+            let mut step_ids: Vec<_> = heads.iter().map(|h| (h.nose_x, h.id.unwrap())).collect();
+            step_ids.sort_by(|a, b| a.0.total_cmp(&b.0));
+            ids.push(step_ids);
+        }
+
+        // the one starting on the left is on the right once they've crossed
+        let ids: Vec<Vec<_>> = ids
+            .iter()
+            .map(|step| step.iter().map(|(_, id)| *id).collect())
+            .collect();
+        let (left, right) = (ids[0][0], ids[0][1]);
+        assert_ne!(left, right);
+        assert_eq!(ids[4], [left]);
+        assert_eq!(ids[5], [left, right]);
+        assert_eq!(ids[9], [right, left]);
+    }
+}
@@ -1,82 +1,78 @@
-use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
-use error_stack::{Result, ResultExt};
-use flume::{Receiver, Sender};
+use error_stack::{Report, Result, ResultExt};
+use flume::{Receiver, RecvTimeoutError, Sender};
 use glam::{Quat, Vec3A};
 
-use std::io::{Read, Write};
+use std::net::Shutdown;
 use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use std::{sync::Arc, u8};
 
+use crate::camera::Frames;
+use crate::protocol::{self, FrameId, Header, MessageKind, PixelFormat};
+use crate::tracker::TrackId;
 use crate::GError;
 use crate::ImageCoords;
 
+/// Frame id, width, height and raw pixels of an image queued for a model.
+pub type ImageRequest = (FrameId, u32, u32, Arc<[u8]>);
+
 pub trait ImageProcessor {
-    fn image_sender(&self) -> &Sender<(u32, u32, Arc<[u8]>)>;
-    fn image_receiver(&self) -> &Receiver<(u32, u32, Arc<[u8]>)>;
+    fn image_sender(&self) -> &Sender<ImageRequest>;
+    fn image_receiver(&self) -> &Receiver<ImageRequest>;
 
-    fn send_img(&self, img: Arc<[u8]>, w: u32, h: u32) -> Result<(), GError> {
+    fn send_img(&self, frame: FrameId, img: Arc<[u8]>, w: u32, h: u32) -> Result<(), GError> {
         self.image_sender()
-            .send((w, h, img))
+            .send((frame, w, h, img))
             .change_context(GError::CommError)
     }
 
-    fn recv_img(&self) -> Result<(u32, u32, Arc<[u8]>), GError> {
+    fn recv_img(&self) -> Result<ImageRequest, GError> {
         self.image_receiver()
             .recv()
             .change_context(GError::CommError)
     }
 }
 
+/// Anything that hands out synchronized image pairs from both cameras.
+pub trait FrameSource: Send {
+    fn next_frames(&mut self, timeout: Duration) -> Result<Frames, GError>;
+}
+
 pub(crate) trait WantIpc {
     fn unix_stream(&self) -> &UnixStream;
 
-    fn send_ipc(&self, msg: &[u8], w: u32, h: u32) -> Result<(), GError> {
-        let msg_len: u32 = msg.len() as u32;
-
-        self.unix_stream()
-            .write_u32::<NetworkEndian>(w)
-            .change_context(GError::IpcError)?;
-        self.unix_stream()
-            .write_u32::<NetworkEndian>(h)
-            .change_context(GError::IpcError)?;
-        self.unix_stream()
-            .write_u32::<NetworkEndian>(msg_len)
-            .change_context(GError::IpcError)?;
-
-        self.unix_stream()
-            .write(msg)
-            .change_context(GError::IpcError)?;
-
-        Ok(())
+    fn send_frame(&self, header: &Header, msg: &[u8]) -> Result<(), GError> {
+        protocol::write_frame(self.unix_stream(), header, msg)
     }
 
-    fn recv_ipc(&self) -> Result<Vec<u8>, GError> {
-        let mut msg = vec![];
+    fn recv_frame(&self) -> Result<(Header, Vec<u8>), GError> {
+        protocol::read_frame(self.unix_stream())
+    }
 
-        let mut msg_len = self
-            .unix_stream()
-            .read_u32::<NetworkEndian>()
-            .change_context(GError::IpcError)? as usize;
+    fn send_ipc(&self, seq: FrameId, msg: &[u8], w: u32, h: u32) -> Result<(), GError> {
+        let header =
+            Header::new(MessageKind::Image, seq, msg.len()).with_image(PixelFormat::Rgb888, w, h);
 
-        let mut buf = [0; 10000];
+        self.send_frame(&header, msg)
+    }
 
-        while msg_len > 0 {
-            let bytes_read = self
-                .unix_stream()
-                .read(&mut buf)
-                .change_context(GError::IpcError)?;
+    fn recv_ipc(&self, kind: MessageKind, seq: FrameId) -> Result<Vec<u8>, GError> {
+        let (header, msg) = self.recv_frame()?;
 
-            msg.extend_from_slice(&buf[..bytes_read]);
-            msg_len -= bytes_read
+        header.expect_kind(kind)?;
+        if header.seq != seq {
+            return Err(GError::ProtocolError).attach_printable(format!(
+                "Expected response to message {}, got {}",
+                seq, header.seq
+            ));
         }
 
         Ok(msg)
     }
 
-    fn send_u32(&self, data: u32) -> Result<(), GError> {
-        self.unix_stream()
-            .write_u32::<NetworkEndian>(data)
-            .change_context(GError::IpcError)
+    fn send_signal(&self, kind: MessageKind, seq: FrameId) -> Result<(), GError> {
+        self.send_frame(&Header::new(kind, seq, 0), &[])
     }
 }
 
@@ -96,6 +92,22 @@ pub trait HasImagePosition {
     fn image_y(&self) -> f32;
 }
 
+impl<T: HasImagePosition> HasImagePosition for &T {
+    fn image_x(&self) -> f32 {
+        (*self).image_x()
+    }
+
+    fn image_y(&self) -> f32 {
+        (*self).image_y()
+    }
+}
+
+/// Detections the tracker can give an id to.
+pub trait HasTrackId {
+    fn track_id(&self) -> Option<TrackId>;
+    fn set_track_id(&mut self, id: TrackId);
+}
+
 pub trait GenProcess {
     type Send;
 
@@ -115,26 +127,131 @@ pub trait GenProcess {
             .recv()
             .change_context(GError::CommError)
     }
+
+    fn recv_data_deadline(&self, deadline: Instant) -> Result<Self::Send, GError> {
+        self.data_receiver()
+            .recv_deadline(deadline)
+            .map_err(timeout_context)
+    }
+
+    fn recv_data_timeout(&self, timeout: Duration) -> Result<Self::Send, GError> {
+        self.recv_data_deadline(Instant::now() + timeout)
+    }
+}
+
+fn timeout_context(e: RecvTimeoutError) -> Report<GError> {
+    match e {
+        RecvTimeoutError::Timeout => Report::new(e).change_context(GError::Timeout),
+        RecvTimeoutError::Disconnected => Report::new(e).change_context(GError::CommError),
+    }
 }
 
 pub trait Responder {
     type Response;
 
-    fn response_sender(&self) -> &Sender<Self::Response>;
-    fn response_receiver(&self) -> &Receiver<Self::Response>;
+    fn response_sender(&self) -> &Sender<(FrameId, Result<Self::Response, GError>)>;
+    fn response_receiver(&self) -> &Receiver<(FrameId, Result<Self::Response, GError>)>;
 
     // TODO: try without map_err
-    fn send_response(&self, res: Self::Response) -> Result<(), GError> {
+    fn send_response(&self, frame: FrameId, res: Self::Response) -> Result<(), GError> {
         self.response_sender()
-            .send(res)
+            .send((frame, Ok(res)))
             .map_err(|_| GError::CommError)
             .change_context(GError::CommError)
             .attach("Failed to send response")
     }
 
-    fn recv_response(&self) -> Result<Self::Response, GError> {
-        self.response_receiver()
-            .recv()
+    /// Hands a failed round trip to the waiting receiver instead of a response.
+    fn send_error(&self, frame: FrameId, err: Report<GError>) -> Result<(), GError> {
+        self.response_sender()
+            .send((frame, Err(err)))
+            .map_err(|_| GError::CommError)
             .change_context(GError::CommError)
+            .attach("Failed to send error")
+    }
+
+    /// Waits for the response to the given frame. Responses to earlier frames
+    /// which were given up on are discarded.
+    fn recv_response(&self, frame: FrameId) -> Result<Self::Response, GError> {
+        loop {
+            let (id, res) = self
+                .response_receiver()
+                .recv()
+                .change_context(GError::CommError)?;
+
+            if id == frame {
+                return res;
+            }
+            println!("Discarding stale response to frame {}", id);
+        }
+    }
+
+    fn recv_response_deadline(
+        &self,
+        frame: FrameId,
+        deadline: Instant,
+    ) -> Result<Self::Response, GError> {
+        loop {
+            let (id, res) = self
+                .response_receiver()
+                .recv_deadline(deadline)
+                .map_err(timeout_context)?;
+
+            if id == frame {
+                return res;
+            }
+            println!("Discarding stale response to frame {}", id);
+        }
+    }
+
+    fn recv_response_timeout(
+        &self,
+        frame: FrameId,
+        timeout: Duration,
+    ) -> Result<Self::Response, GError> {
+        self.recv_response_deadline(frame, Instant::now() + timeout)
+    }
+}
+
+/// A connection to a process that can be swapped for a new one when the process restarts.
+pub(crate) trait Reconnect: WantIpc + Sized {
+    fn stream_sender(&self) -> &Sender<UnixStream>;
+    fn stream_receiver(&self) -> &Receiver<UnixStream>;
+    fn liveness(&self) -> &AtomicBool;
+    fn set_unix_stream(&mut self, stream: UnixStream);
+
+    fn is_up(&self) -> bool {
+        self.liveness().load(Ordering::Acquire)
+    }
+
+    /// Hangs up on the process, a hung one can't answer late into the next request.
+    fn mark_down(&self) {
+        self.liveness().store(false, Ordering::Release);
+        let _ = self.unix_stream().shutdown(Shutdown::Both);
+    }
+
+    /// Queues a newly connected stream, it is picked up by the worker thread on its next request.
+    fn replace_stream(&self, stream: UnixStream) -> Result<(), GError> {
+        self.stream_sender()
+            .send(stream)
+            .map_err(|_| GError::CommError)
+            .change_context(GError::CommError)
+            .attach("Failed to hand over stream")
+    }
+
+    /// Switches to the most recently queued replacement stream, if any. The newest
+    /// connection always wins since a crashed process is only noticed on its next request.
+    /// Returns true if the connection was replaced.
+    fn reconnect(&mut self) -> bool {
+        let latest = self.stream_receiver().try_iter().last();
+
+        match latest {
+            Some(stream) => {
+                self.set_unix_stream(stream);
+                self.liveness().store(true, Ordering::Release);
+                true
+            }
+            None => false,
+        }
     }
 }
@@ -18,6 +18,8 @@ DETECTION_RESULT = None
 # Load TFLite model and allocate tensors.
 interpreter = tf.lite.Interpreter(model_path="lite_gesture_model.tflite")
 label_map = np.load("lable_map.npy", allow_pickle=True).item()
+# labels of the model the orchestrator knows by another name
+LABEL_NAMES = {"One_Hand_Up": "Toggle"}
 
 input_details = interpreter.get_input_details()
 output_details = interpreter.get_output_details()
@@ -60,6 +62,7 @@ def preprocess_image(img, w, h):
     mp_image = mp.Image(image_format=mp.ImageFormat.SRGB, data=img)
     detector.detect_async(mp_image, time.time_ns() // 1_000_000)
     nose_coords = []
+    values = []
     # Draw pose landmarks on the frame for upper body parts only
     if DETECTION_RESULT is not None:
         keypoints = []
@@ -71,6 +74,7 @@ def preprocess_image(img, w, h):
                     # cx, cy = int(landmark.x * w), int(landmark.y * h)
                     # cv2.circle(frame, (cx, cy), 5, (255, 0, 0), -1)
             nose_coords.append((temp[0][0]*w,temp[0][1]*h))
+            values.append(hand_height(np.array(temp)))
             data = np.array(temp)
             center_x = data[:, 0].mean()
             center_y = data[:, 1].mean()
@@ -81,9 +85,29 @@ def preprocess_image(img, w, h):
             data[:, 2] = (data[:, 2] - center_z) * 500  # Z coordinates
             keypoints.append(data)
         keypoints = np.array(keypoints)
-        return keypoints, nose_coords
+        return keypoints, nose_coords, values
 
-    return None,None
+    return None, None, None
+
+
+def hand_height(landmarks):
+    """Height of the higher hand from 0 to 1, sent as the value of the gesture.
+
+    Takes the upper body landmarks in the order of UPPER_BODY_PARTS. 0 is the
+    arm hanging straight down, 0.5 the hand level with its shoulder and 1 the
+    arm stretched straight up, measured in arm lengths so it doesn't matter
+    how far from the camera someone stands.
+    """
+    heights = []
+    for shoulder, elbow, wrist in ((3, 5, 7), (4, 6, 8)):
+        arm = np.linalg.norm(landmarks[elbow, :2] - landmarks[shoulder, :2]) + np.linalg.norm(
+            landmarks[wrist, :2] - landmarks[elbow, :2]
+        )
+        if arm > 0:
+            # image y grows downwards
+            raised = (landmarks[shoulder, 1] - landmarks[wrist, 1]) / arm
+            heights.append((raised + 1) / 2)
+    return float(np.clip(max(heights, default=0.0), 0.0, 1.0))
 
 
 def predict_gesture(data):
@@ -103,61 +127,101 @@ def predict_gesture(data):
     label = label_map[output]
 
     if prediction[0][output] < 0.9:
-        label = "None"
+        return "None"
 
-    if label == "One_Hand_Up":
-        label = "Toggle"
-    else:
-        label = "None"
-
-    return label
+    # every other label is passed on as is, see Gesture in
+    # app/src/models/gesture_recognition.rs for the names understood
+    return LABEL_NAMES.get(label, label)
 
 
 config = {
     "process_id": "gesture",
+    "version": "0.1.0",
+    "model": "lite_gesture_model",
     "server_address": "/tmp/gesurease.sock",
 }
 
 
-def run():
-    img_width_bytes = sock.recv(4)
-    img_height_bytes = sock.recv(4)
-    data_len_bytes = sock.recv(4)
-    if len(data_len_bytes) == 0:
-        print("Connection closed, exiting...")
+# framed IPC protocol, see app/src/protocol.rs
+MAGIC = 0x47455345
+PROTOCOL_VERSION = 1
+HEADER = struct.Struct("!IHBBIIII")
+KIND_HELLO, KIND_IMAGE, KIND_PREDICTION, KIND_CAPTURE, KIND_CONFIGURE, KIND_ERROR = range(6)
+PIXEL_NONE, PIXEL_RGB888 = 0, 1
+
+
+def recv_exact(n):
+    data = b""
+    while len(data) < n:
+        chunk = sock.recv(n - len(data))
+        if len(chunk) == 0:
+            print("Connection closed, exiting...")
+            exit(1)
+        data += chunk
+    return data
+
+
+def recv_frame():
+    magic, version, kind, pixel_format, seq, w, h, length = HEADER.unpack(
+        recv_exact(HEADER.size)
+    )
+    if magic != MAGIC or version != PROTOCOL_VERSION:
+        print("Peer speaks an unknown protocol, exiting...")
+        exit(1)
+    return kind, pixel_format, seq, w, h, recv_exact(length)
+
+
+def send_frame(kind, seq, payload=b"", pixel_format=PIXEL_NONE, w=0, h=0):
+    sock.sendall(
+        HEADER.pack(MAGIC, PROTOCOL_VERSION, kind, pixel_format, seq, w, h, len(payload))
+    )
+    sock.sendall(payload)
+
+
+def hello():
+    msg = {
+        "process": config["process_id"],
+        "version": config["version"],
+        "pixel_formats": ["rgb888"],
+        "model": config["model"],
+        "max_batch": 1,
+    }
+    send_frame(KIND_HELLO, 0, json.dumps(msg).encode())
+    kind, _, _, _, _, payload = recv_frame()
+    if kind != KIND_HELLO:
+        print("Rejected by server:", payload.decode(errors="replace"))
         exit(1)
 
-    img_width = struct.unpack("!I", img_width_bytes)[0]
-    img_height = struct.unpack("!I", img_height_bytes)[0]
-    data_len = struct.unpack("!I", data_len_bytes)[0]
 
-    img = sock.recv(data_len)
-    while len(img) < data_len:
-        img += sock.recv(data_len - len(img))
+def run():
+    kind, _, seq, img_width, img_height, img = recv_frame()
+    if kind != KIND_IMAGE:
+        return
 
     # print(img)
-    key_points_multiple_person, nose_coords = preprocess_image(
+    key_points_multiple_person, nose_coords, values = preprocess_image(
         img, img_width, img_height
     )
 
     if key_points_multiple_person is not None:
         gesture_prediction = []
         for idx, key_points in enumerate(key_points_multiple_person):
-            gesture_prediction.append([predict_gesture(key_points), nose_coords[idx]])
+            gesture_prediction.append(
+                [predict_gesture(key_points), nose_coords[idx], values[idx]]
+            )
         json_data = []
         for i in gesture_prediction:
-            dict = {"gesture": i[0], "nose_x": i[1][0], "nose_y": i[1][1]}
+            # value is the hand height, see hand_height
+            dict = {"gesture": i[0], "nose_x": i[1][0], "nose_y": i[1][1], "value": i[2]}
             json_data.append(dict)
 
-        json_response = json.dumps({"prediction": json_data})
-        sock.sendall(struct.pack("!I", len(json_response)))
-        sock.sendall(json_response.encode())
+        json_response = json.dumps({"frame": seq, "prediction": json_data})
+        send_frame(KIND_PREDICTION, seq, json_response.encode())
     else:
         gesture_prediction = json.dumps(
-            {"prediction": [{"gesture": "None", "nose_x": 0.0, "nose_y": 0.0}]}
+            {"frame": seq, "prediction": [{"gesture": "None", "nose_x": 0.0, "nose_y": 0.0}]}
         )
-        sock.sendall(struct.pack("!I", len(gesture_prediction)))
-        sock.sendall(gesture_prediction.encode())
+        send_frame(KIND_PREDICTION, seq, gesture_prediction.encode())
     time.sleep(0.1)
 
 if __name__ == "__main__":
@@ -166,7 +230,7 @@ if __name__ == "__main__":
     sock.setblocking(True)
 
     # Send the process identifier to the Rust server
-    sock.sendall(config["process_id"].encode())
+    hello()
 
     while True:
         run()
@@ -56,25 +56,67 @@ def preprocess_image(img, w, h):
 
 config = {
     "process_id": "head",
+    "version": "0.1.0",
+    "model": "pose_landmarker_lite",
     "server_address": "/tmp/gesurease.sock",
 }
 
 
-def run():
-    img_width_bytes = sock.recv(4)
-    img_height_bytes = sock.recv(4)
-    data_len_bytes = sock.recv(4)
-    if len(data_len_bytes) == 0:
-        print("Connection closed, exiting...")
+# framed IPC protocol, see app/src/protocol.rs
+MAGIC = 0x47455345
+PROTOCOL_VERSION = 1
+HEADER = struct.Struct("!IHBBIIII")
+KIND_HELLO, KIND_IMAGE, KIND_PREDICTION, KIND_CAPTURE, KIND_CONFIGURE, KIND_ERROR = range(6)
+PIXEL_NONE, PIXEL_RGB888 = 0, 1
+
+
+def recv_exact(n):
+    data = b""
+    while len(data) < n:
+        chunk = sock.recv(n - len(data))
+        if len(chunk) == 0:
+            print("Connection closed, exiting...")
+            exit(1)
+        data += chunk
+    return data
+
+
+def recv_frame():
+    magic, version, kind, pixel_format, seq, w, h, length = HEADER.unpack(
+        recv_exact(HEADER.size)
+    )
+    if magic != MAGIC or version != PROTOCOL_VERSION:
+        print("Peer speaks an unknown protocol, exiting...")
+        exit(1)
+    return kind, pixel_format, seq, w, h, recv_exact(length)
+
+
+def send_frame(kind, seq, payload=b"", pixel_format=PIXEL_NONE, w=0, h=0):
+    sock.sendall(
+        HEADER.pack(MAGIC, PROTOCOL_VERSION, kind, pixel_format, seq, w, h, len(payload))
+    )
+    sock.sendall(payload)
+
+
+def hello():
+    msg = {
+        "process": config["process_id"],
+        "version": config["version"],
+        "pixel_formats": ["rgb888"],
+        "model": config["model"],
+        "max_batch": 1,
+    }
+    send_frame(KIND_HELLO, 0, json.dumps(msg).encode())
+    kind, _, _, _, _, payload = recv_frame()
+    if kind != KIND_HELLO:
+        print("Rejected by server:", payload.decode(errors="replace"))
         exit(1)
 
-    img_width = struct.unpack("!I", img_width_bytes)[0]
-    img_height = struct.unpack("!I", img_height_bytes)[0]
-    data_len = struct.unpack("!I", data_len_bytes)[0]
 
-    img = sock.recv(data_len)
-    while len(img) < data_len:
-        img += sock.recv(data_len - len(img))
+def run():
+    kind, _, seq, img_width, img_height, img = recv_frame()
+    if kind != KIND_IMAGE:
+        return
 
 
     # print(img)
@@ -86,15 +128,13 @@ def run():
             dict = {"nose_x": i[0]*img_width, "nose_y": i[1]*img_height}
             json_data.append(dict)
 
-        json_response = json.dumps({"prediction": json_data})
-        sock.sendall(struct.pack("!I", len(json_response)))
-        sock.sendall(json_response.encode())
+        json_response = json.dumps({"frame": seq, "prediction": json_data})
+        send_frame(KIND_PREDICTION, seq, json_response.encode())
     else:
         json_response = json.dumps(
-            {"prediction": [{"nose_x": 0.0, "nose_y": 0.0}]}
+            {"frame": seq, "prediction": [{"nose_x": 0.0, "nose_y": 0.0}]}
         )
-        sock.sendall(struct.pack("!I", len(json_response)))
-        sock.sendall(json_response.encode())
+        send_frame(KIND_PREDICTION, seq, json_response.encode())
     time.sleep(0.1)
 
 if __name__ == "__main__":
@@ -103,7 +143,7 @@ if __name__ == "__main__":
     sock.setblocking(True)
 
     # Send the process identifier to the Rust server
-    sock.sendall(config["process_id"].encode())
+    hello()
 
     while True:
         run()
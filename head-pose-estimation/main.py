@@ -19,6 +19,8 @@ opt_model = model
 
 config = {
     "process_id": "directmhp",
+    "version": "0.1.0",
+    "model": "agora_m_best",
     "server_address": "/tmp/gesurease.sock",
     "img_size": 320,
     "stride": model.model.stride.max().item(),
@@ -59,7 +61,7 @@ def to_radiants(pitch_yaw_roll: np.ndarray):
     return pitch_yaw_roll
 
 
-def pred(img, w, h):
+def pred(img, w, h, seq):
     # img = np.array(Image.open(io.BytesIO(img)).convert(mode="RGB"))
     img = np.frombuffer(img, np.uint8).reshape(h, w, 3)
 
@@ -85,39 +87,78 @@ def pred(img, w, h):
     out = [t.cpu().detach().numpy().tolist() for t in out]
     out = [dict(zip(config["prediction"], pred)) for pred in out]
 
-    return json.dumps({"prediction": out})
+    return json.dumps({"frame": seq, "prediction": out})
 
 
-def run():
-    img_width_bytes = sock.recv(4)
-    img_height_bytes = sock.recv(4)
-    data_len_bytes = sock.recv(4)
-    if len(data_len_bytes) == 0:
-        print("Connection closed, exiting...")
+# framed IPC protocol, see app/src/protocol.rs
+MAGIC = 0x47455345
+PROTOCOL_VERSION = 1
+HEADER = struct.Struct("!IHBBIIII")
+KIND_HELLO, KIND_IMAGE, KIND_PREDICTION, KIND_CAPTURE, KIND_CONFIGURE, KIND_ERROR = range(6)
+PIXEL_NONE, PIXEL_RGB888 = 0, 1
+
+
+def recv_exact(n):
+    data = b""
+    while len(data) < n:
+        chunk = sock.recv(n - len(data))
+        if len(chunk) == 0:
+            print("Connection closed, exiting...")
+            exit(1)
+        data += chunk
+    return data
+
+
+def recv_frame():
+    magic, version, kind, pixel_format, seq, w, h, length = HEADER.unpack(
+        recv_exact(HEADER.size)
+    )
+    if magic != MAGIC or version != PROTOCOL_VERSION:
+        print("Peer speaks an unknown protocol, exiting...")
         exit(1)
+    return kind, pixel_format, seq, w, h, recv_exact(length)
+
+
+def send_frame(kind, seq, payload=b"", pixel_format=PIXEL_NONE, w=0, h=0):
+    sock.sendall(
+        HEADER.pack(MAGIC, PROTOCOL_VERSION, kind, pixel_format, seq, w, h, len(payload))
+    )
+    sock.sendall(payload)
+
+
+def hello():
+    msg = {
+        "process": config["process_id"],
+        "version": config["version"],
+        "pixel_formats": ["rgb888"],
+        "model": config["model"],
+        "max_batch": 1,
+    }
+    send_frame(KIND_HELLO, 0, json.dumps(msg).encode())
+    kind, _, _, _, _, payload = recv_frame()
+    if kind != KIND_HELLO:
+        print("Rejected by server:", payload.decode(errors="replace"))
+        exit(1)
+
 
-    img_width = struct.unpack("!I", img_width_bytes)[0]
-    img_height = struct.unpack("!I", img_height_bytes)[0]
-    data_len = struct.unpack("!I", data_len_bytes)[0]
+def run():
+    kind, _, seq, img_width, img_height, img = recv_frame()
+    if kind != KIND_IMAGE:
+        return
 
     if config["debug"]:
         start = time.time()
-    img = sock.recv(data_len)
-    while len(img) < data_len:
-        img += sock.recv(data_len - len(img))
-
     # print(img)
 
     if config["debug"]:
         start2 = time.time()
 
-    preds = pred(img, img_width, img_height)
+    preds = pred(img, img_width, img_height, seq)
 
     if config["debug"]:
         end2 = (time.time() - start2) * 1000
 
-    sock.sendall(struct.pack("!I", len(preds)))
-    sock.sendall(preds.encode())
+    send_frame(KIND_PREDICTION, seq, preds.encode())
 
     if config["debug"]:
         end = (time.time() - start) * 1000
@@ -133,7 +174,7 @@ if __name__ == "__main__":
     sock.setblocking(True)
 
     # Send the process identifier to the Rust server
-    sock.sendall(config["process_id"].encode())
+    hello()
 
     while True:
         run()
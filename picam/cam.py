@@ -10,7 +10,12 @@ from queue import Queue
 HOST = "localhost"  # Replace with your Rust application's IPC endpoint
 PORT = 5555  # Replace with the port number your Rust application listens on
 
-config = {"process_id": "cam", "server_address": "/tmp/gesurease.sock"}
+config = {
+    "process_id": "cam",
+    "version": "0.1.0",
+    "model": "picamera2",
+    "server_address": "/tmp/gesurease.sock",
+}
 
 cam1_send_q = Queue()
 cam1_receive_q = Queue()
@@ -42,16 +47,67 @@ def capture_and_send(camera_id, qs, qr, w, h):
         picam2.stop()
 
 
-def run():
-    get = sock.recv(4)
-    if len(get) == 0:
-        print("Connection closed, exiting...")
-        cam1_send_q.put_nowait(False)
-        cam2_send_q.put_nowait(False)
+# framed IPC protocol, see app/src/protocol.rs
+MAGIC = 0x47455345
+PROTOCOL_VERSION = 1
+HEADER = struct.Struct("!IHBBIIII")
+KIND_HELLO, KIND_IMAGE, KIND_PREDICTION, KIND_CAPTURE, KIND_CONFIGURE, KIND_ERROR = range(6)
+PIXEL_NONE, PIXEL_RGB888 = 0, 1
+
+
+def stop_cams():
+    cam1_send_q.put_nowait(False)
+    cam2_send_q.put_nowait(False)
+
+
+def recv_exact(n):
+    data = b""
+    while len(data) < n:
+        chunk = sock.recv(n - len(data))
+        if len(chunk) == 0:
+            print("Connection closed, exiting...")
+            stop_cams()
+            exit(1)
+        data += chunk
+    return data
+
+
+def recv_frame():
+    magic, version, kind, pixel_format, seq, w, h, length = HEADER.unpack(
+        recv_exact(HEADER.size)
+    )
+    if magic != MAGIC or version != PROTOCOL_VERSION:
+        print("Peer speaks an unknown protocol, exiting...")
+        stop_cams()
+        exit(1)
+    return kind, pixel_format, seq, w, h, recv_exact(length)
+
+
+def send_frame(kind, seq, payload=b"", pixel_format=PIXEL_NONE, w=0, h=0):
+    sock.sendall(
+        HEADER.pack(MAGIC, PROTOCOL_VERSION, kind, pixel_format, seq, w, h, len(payload))
+    )
+    sock.sendall(payload)
+
+
+def hello():
+    msg = {
+        "process": config["process_id"],
+        "version": config["version"],
+        "pixel_formats": ["rgb888"],
+        "model": config["model"],
+        "max_batch": 1,
+    }
+    send_frame(KIND_HELLO, 0, json.dumps(msg).encode())
+    kind, _, _, _, _, payload = recv_frame()
+    if kind != KIND_HELLO:
+        print("Rejected by server:", payload.decode(errors="replace"))
         exit(1)
 
-    get = struct.unpack("!I", get)[0]
-    if get != 1:
+
+def run():
+    kind, _, seq, _, _, _ = recv_frame()
+    if kind != KIND_CAPTURE:
         return
 
     cam1_send_q.put_nowait(True)
@@ -60,16 +116,8 @@ def run():
     img1 = cam1_receive_q.get(timeout=2)
     img2 = cam2_receive_q.get(timeout=2)
 
-    sock.sendall(struct.pack("!I", len(img1)))
-    sock.sendall(img1)
-
-    _send = sock.recv(4)
-    _send = struct.unpack("!I", _send)[0]
-    if _send != 2:
-        return
-
-    sock.sendall(struct.pack("!I", len(img2)))
-    sock.sendall(img2)
+    send_frame(KIND_IMAGE, seq, img1, PIXEL_RGB888, w1, h1)
+    send_frame(KIND_IMAGE, seq, img2, PIXEL_RGB888, w2, h2)
 
 
 if __name__ == "__main__":
@@ -77,15 +125,13 @@ if __name__ == "__main__":
     sock.connect(config["server_address"])
     sock.setblocking(True)
 
-    sock.sendall(config["process_id"].encode())
+    hello()
 
     print("Starting Cams. Waiting for img dimensions...")
 
-    w1 = struct.unpack("!I", sock.recv(4))[0]
-    h1 = struct.unpack("!I", sock.recv(4))[0]
-
-    w2 = struct.unpack("!I", sock.recv(4))[0]
-    h2 = struct.unpack("!I", sock.recv(4))[0]
+    # the sequence id of a configure message is the camera number
+    _, _, _, w1, h1, _ = recv_frame()
+    _, _, _, w2, h2, _ = recv_frame()
 
     print("Dimensions received.")
     print(w1, h1, w2, h2)